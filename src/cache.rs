@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+use log::Level;
+use mdbook_preprocessor::book::Chapter;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Configuration;
+use crate::logging::{self, Diagnostic};
+use crate::preprocessor::{ChapterReport, highlight_chapter};
+
+/// Directory of the cache inside the build directory of the book.
+pub(crate) const CACHE_DIR: &str = "inline-highlighting-cache";
+
+/// The highlighted chapters of an earlier run, keyed by a hash of their content and
+/// the configuration.
+pub(crate) struct Cache {
+    dir: PathBuf,
+    /// The entries used by this run, which [`Cache::prune`] keeps.
+    used: Mutex<HashSet<PathBuf>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    languages: BTreeMap<String, usize>,
+    content: String,
+    /// The messages logged while highlighting the chapter, logged again when the
+    /// entry is used.
+    diagnostics: Vec<CachedDiagnostic>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedDiagnostic {
+    level: String,
+    message: String,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Cache {
+            dir,
+            used: Mutex::default(),
+        }
+    }
+
+    /// Highlights `chapter` unless the cache has a result for its content already,
+    /// storing the result otherwise.
+    pub fn highlight(&self, chapter: &mut Chapter, config: &Configuration) -> ChapterReport {
+        let path = self.dir.join(format!("{:016x}.json", key(chapter, config)));
+        self.used
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.clone());
+        let cached = fs::read_to_string(&path)
+            .ok()
+            .and_then(|entry| serde_json::from_str::<Entry>(&entry).ok())
+            .and_then(|entry| {
+                let diagnostics = entry
+                    .diagnostics
+                    .iter()
+                    .map(|diagnostic| {
+                        Some(Diagnostic {
+                            level: diagnostic.level.parse().ok()?,
+                            message: diagnostic.message.clone(),
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Some((entry, diagnostics))
+            });
+        if let Some((entry, diagnostics)) = cached {
+            logging::replay(config, diagnostics);
+            chapter.content = entry.content;
+            return ChapterReport {
+                chapter: chapter.name.clone(),
+                languages: entry.languages,
+            };
+        }
+        let (report, diagnostics) = logging::collect(|| highlight_chapter(chapter, config));
+        let entry = Entry {
+            languages: report.languages.clone(),
+            content: chapter.content.clone(),
+            diagnostics: diagnostics
+                .into_iter()
+                .map(|diagnostic| CachedDiagnostic {
+                    level: diagnostic.level.to_string(),
+                    message: diagnostic.message,
+                })
+                .collect(),
+        };
+        let stored = fs::create_dir_all(&self.dir)
+            .and_then(|()| fs::write(&path, serde_json::to_string(&entry).unwrap_or_default()));
+        if let Err(error) = stored {
+            logging::log(
+                config,
+                Level::Warn,
+                format_args!("cannot cache chapter `{}`: {}", chapter, error),
+            );
+        }
+        report
+    }
+
+    /// Removes the entries not used by this run, which belong to chapters or
+    /// configurations that no longer exist.
+    pub fn prune(&self, config: &Configuration) {
+        let Ok(files) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let used = self.used.lock().unwrap_or_else(PoisonError::into_inner);
+        for path in files.flatten().map(|file| file.path()) {
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+                && !used.contains(&path)
+                && let Err(error) = fs::remove_file(&path)
+            {
+                logging::log(
+                    config,
+                    Level::Warn,
+                    format_args!("cannot remove cache entry `{}`: {}", path.display(), error),
+                );
+            }
+        }
+    }
+}
+
+/// Returns the cache key of `chapter`, which changes with its content, the
+/// configuration and the version of this crate.
+fn key(chapter: &Chapter, config: &Configuration) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    format!("{:?}", config).hash(&mut hasher);
+    chapter.content.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reuses_cached_chapters() {
+        let dir = std::env::temp_dir().join(format!(
+            "mdbook-inline-highlighting-cache-{}",
+            std::process::id()
+        ));
+        let cache = Cache::new(dir.clone());
+        let config = Configuration::default();
+        let new_chapter = |content: &str| Chapter {
+            content: content.to_string(),
+            ..Default::default()
+        };
+
+        let mut chapter = new_chapter("`[rust] x`");
+        let report = cache.highlight(&mut chapter, &config);
+        assert_eq!(
+            "<code class=\"hljs language-rust\">x</code>",
+            chapter.content
+        );
+        assert_eq!(1, report.languages["rust"]);
+
+        // A cached chapter is taken from the cache instead of being highlighted.
+        let path = dir.join(format!(
+            "{:016x}.json",
+            key(&new_chapter("`[rust] x`"), &config)
+        ));
+        let entry = Entry {
+            languages: [("rust".to_string(), 2)].into(),
+            content: "cached".to_string(),
+            diagnostics: vec![],
+        };
+        fs::write(&path, serde_json::to_string(&entry).unwrap()).unwrap();
+        let mut chapter = new_chapter("`[rust] x`");
+        let report = cache.highlight(&mut chapter, &config);
+        assert_eq!("cached", chapter.content);
+        assert_eq!(2, report.languages["rust"]);
+
+        let mut chapter = new_chapter("`[py] y`");
+        cache.highlight(&mut chapter, &config);
+        assert_eq!("<code class=\"hljs language-py\">y</code>", chapter.content);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replays_cached_diagnostics() {
+        let dir = std::env::temp_dir().join(format!(
+            "mdbook-inline-highlighting-cache-diagnostics-{}",
+            std::process::id()
+        ));
+        let cache = Cache::new(dir.clone());
+        let config = Configuration::default();
+        let highlight = || {
+            let mut chapter = Chapter {
+                content: "`[rust]x`".to_string(),
+                ..Default::default()
+            };
+            logging::capture::logged(|| {
+                cache.highlight(&mut chapter, &config);
+            })
+        };
+        let records = highlight();
+        assert_eq!(1, records.len());
+        assert_eq!(Level::Error, records[0].0);
+        assert_eq!(records, highlight());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn prunes_unused_entries() {
+        let dir = std::env::temp_dir().join(format!(
+            "mdbook-inline-highlighting-cache-prune-{}",
+            std::process::id()
+        ));
+        let config = Configuration::default();
+        let new_chapter = |content: &str| Chapter {
+            content: content.to_string(),
+            ..Default::default()
+        };
+        let entry =
+            |content: &str| dir.join(format!("{:016x}.json", key(&new_chapter(content), &config)));
+
+        let cache = Cache::new(dir.clone());
+        cache.highlight(&mut new_chapter("`[rust] x`"), &config);
+        cache.highlight(&mut new_chapter("`[py] y`"), &config);
+        cache.prune(&config);
+        assert!(entry("`[rust] x`").exists());
+        assert!(entry("`[py] y`").exists());
+
+        // A later run without the first chapter removes its entry.
+        let cache = Cache::new(dir.clone());
+        cache.highlight(&mut new_chapter("`[py] y`"), &config);
+        cache.prune(&config);
+        assert!(!entry("`[rust] x`").exists());
+        assert!(entry("`[py] y`").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,15 +1,1155 @@
-#[derive(Default)]
-pub(crate) struct Configuration {
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use log::Level;
+use mdbook_preprocessor::config::Config;
+use mdbook_preprocessor::errors::{Error, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::logging;
+
+const CONFIG_PREFIX: &str = "preprocessor.inline-highlighting";
+const DEFAULT_SEPARATOR: &str = " ";
+const DEFAULT_MARK_LANGUAGE: &str = "mark";
+/// Pseudo-languages of `element-map` unless configured otherwise.
+const DEFAULT_ELEMENT_MAP: &[(&str, &str)] = &[("var", "var")];
+/// Groups accepted by `language-groups`.
+const LANGUAGE_GROUPS: &[&str] = &["data", "markup", "query", "scripting", "shell", "systems"];
+
+/// Named bundles of defaults selected with `preset`, as TOML.
+const PRESETS: &[(&str, &str)] = &[
+    (
+        "highlightjs",
+        r#"
+        base-class = "hljs"
+        language-marking = "both"
+        element = "code"
+        "#,
+    ),
+    (
+        "prism",
+        r#"
+        base-class = ""
+        language-marking = "class"
+        element = "code"
+        "#,
+    ),
+    (
+        "accessible",
+        r#"
+        original-as-title = true
+        noscript-fallback = true
+        language-badge = true
+        force-ltr = true
+        "#,
+    ),
+];
+
+/// Every key of the `[preprocessor.inline-highlighting]` table with its default
+/// value, empty if the key is unset by default.
+const CONFIG_KEYS: &[(&str, &str)] = &[
+    ("config-strictness", "lenient"),
+    ("preset", ""),
+    ("default-language", ""),
+    ("comment-spec", "false"),
+    ("syntax", "brackets"),
+    ("sigil", ""),
+    ("highlight-unmarked", "true"),
+    ("code-pattern", ""),
+    ("aliases", "{}"),
+    ("language-separators", "{}"),
+    ("separator-class", "space"),
+    ("mark-language", "mark"),
+    ("element", "code"),
+    ("auto-element", ""),
+    ("target", "html"),
+    ("emission", "raw-html"),
+    ("element-map", "{ var = \"var\" }"),
+    ("language-groups", "{}"),
+    ("language-affixes", "{}"),
+    ("always-class", ""),
+    ("dual-theme-classes", "false"),
+    ("theme-classes", "[\"hljs-light\", \"hljs-dark\"]"),
+    ("final-class-map", "{}"),
+    ("doc-links", "{}"),
+    ("css-var-map", "{}"),
+    ("normalize-language", "false"),
+    ("known-languages", "[]"),
+    ("allowed-languages", "[]"),
+    ("bare-language", "false"),
+    ("empty-code-policy", "emit-empty"),
+    ("require-separator", "true"),
+    ("body-strip-prefix", ""),
+    ("body-strip-suffix", ""),
+    ("min-language-length", "0"),
+    ("collapse-escapes", "false"),
+    ("doubled-delimiter-escape", "false"),
+    ("base-class", "hljs"),
+    ("no-base-class-for", "[]"),
+    ("base-class-for", "{}"),
+    ("base-class-case", "as-is"),
+    ("language-marking", "class"),
+    ("data-lang", "false"),
+    ("copy-button-attr", ""),
+    ("original-as-title", "false"),
+    ("record-original-language", "false"),
+    ("tab-replacement", ""),
+    ("strip-trailing-punct", ""),
+    ("token-wrap", "false"),
+    ("preserve-whitespace", "false"),
+    ("force-ltr", "false"),
+    ("nonce", ""),
+    ("preserve-whitespace-class", ""),
+    ("inner-wrapper-class", ""),
+    ("source-position-attrs", "false"),
+    ("data-element", "false"),
+    ("noscript-fallback", "false"),
+    ("clipboard-source", "false"),
+    ("language-badge", "false"),
+    ("auto-detect-class", ""),
+    ("auto-class", ""),
+    ("preserve-fence-char", "false"),
+    ("preserve-emphasis-char", "false"),
+    ("preserve-heading-style", "false"),
+    ("theme-aware-plain", "false"),
+    ("math", "false"),
+    ("front-matter", "false"),
+    ("merge-adjacent", "false"),
+    ("skip-drafts", "false"),
+    ("sections", "[]"),
+    ("enable-cache", "false"),
+    ("warn-unused-default", "false"),
+    ("validate-html", "false"),
+    ("detect-misfenced", ""),
+    ("misfenced-plain", "false"),
+    ("normalize-output", "false"),
+    ("warn-on-large-output", ""),
+    ("serialize-buffer-capacity", ""),
+    ("generate-stats-chapter", "false"),
+    ("stats-chapter-title", "Language Statistics"),
+    ("stats-chapter-path", "language-statistics.md"),
+    ("report-languages", "false"),
+    ("dump-config", "false"),
+    ("no-logging", "false"),
+];
+
+#[derive(Debug, Default)]
+pub struct Configuration {
+    /// Bundle of defaults of the other options, e.g. `prism`.
+    pub preset: Option<String>,
+    /// Whether values of the wrong type are ignored or an error.
+    pub config_strictness: Strictness,
     pub default_language: Option<String>,
+    /// Accepts the language in a leading HTML comment, e.g. `<!--rust--> code`, too.
+    pub comment_spec: bool,
+    /// Whether the language is specified in brackets or after a sigil.
+    pub syntax: Syntax,
+    /// Character starting the language with `syntax = "sigil"`, `#` if absent.
+    pub sigil: Option<char>,
+    /// Whether inline code without a language specification, or with `[none]`, is
+    /// highlighted with the default language, `true` if absent.
+    pub highlight_unmarked: Option<bool>,
+    /// Unmarked inline code is only highlighted with the default language if it
+    /// matches this pattern.
+    pub code_pattern: Option<Regex>,
+    /// Maps alternative language names to their canonical name, e.g. `js = "javascript"`.
+    pub aliases: BTreeMap<String, String>,
+    /// Separators between the language specification and the code overriding the
+    /// default space for some languages.
+    pub language_separators: BTreeMap<String, String>,
+    /// Which whitespace characters count as the default separator.
+    pub separator_class: SeparatorClass,
+    /// Pseudo-language marking inline code with `<mark>` instead of highlighting it,
+    /// `mark` if absent and none if empty.
+    pub mark_language: Option<String>,
+    /// Element of highlighted inline code, `code` if absent.
+    pub element: Option<String>,
+    /// Element of auto-detected (`[auto]`) inline code, `element` if absent.
+    pub auto_element: Option<String>,
+    /// What highlighted code is emitted as, HTML or JSX elements.
+    pub target: Target,
+    /// How the generated markup is put into the chapter.
+    pub emission: Emission,
+    /// Pseudo-languages emitted as the given element instead of highlighted `<code>`
+    /// besides `var = "var"`, which an empty element turns off.
+    pub element_map: BTreeMap<String, String>,
+    /// Maps languages to one of a fixed set of groups emitted as `group-*` class.
+    pub language_groups: BTreeMap<String, String>,
+    /// Text inserted before and after the code of some languages, e.g. REPL prompts.
+    pub language_affixes: BTreeMap<String, Affixes>,
+    /// Class added to every highlighted element.
+    pub always_class: Option<String>,
+    /// Adds the classes of `theme_classes` to highlighted elements so stylesheets
+    /// can pick one, e.g. by `prefers-color-scheme`.
+    pub dual_theme_classes: bool,
+    /// Classes of `dual_theme_classes`, `hljs-light` and `hljs-dark` if empty.
+    pub theme_classes: Vec<String>,
+    /// Classes replacing the composed classes of some languages entirely.
+    pub final_class_map: BTreeMap<String, String>,
+    /// URLs of the documentation of some languages linked from their highlighted
+    /// inline code, `{code}` being replaced by the code.
+    pub doc_links: BTreeMap<String, String>,
+    /// CSS variables of some languages set as `--lang-color` in the style of their
+    /// highlighted inline code.
+    pub css_var_map: BTreeMap<String, String>,
+    /// Lowercases language specifications, also non-ASCII ones, before resolving
+    /// aliases.
+    pub normalize_language: bool,
+    /// Languages the book may use if not empty, any other language is reported,
+    /// and an error with strict `config-strictness`.
+    pub allowed_languages: Vec<String>,
+    /// Languages recognized by `bare-language`.
+    pub known_languages: Vec<String>,
+    /// Treats a leading known language followed by whitespace as the language of
+    /// inline code without brackets, e.g. `rust fn main() {}`.
+    pub bare_language: bool,
+    /// What inline code consisting of only a language specification and separator
+    /// turns into.
+    pub empty_code_policy: EmptyCodePolicy,
+    /// Whether a language specification has to be followed by a separator, `true` if
+    /// absent.
+    pub require_separator: Option<bool>,
+    /// Marker removed from the start of the code following a language specification
+    /// if the code ends with `body-strip-suffix` as well.
+    pub body_strip_prefix: Option<String>,
+    /// Marker removed from the end of the code together with `body-strip-prefix`.
+    pub body_strip_suffix: Option<String>,
+    /// Languages shorter than this are taken for code rather than a language.
+    pub min_language_length: usize,
+    /// Treats each escape character of a leading run as escaping the next one
+    /// instead of removing only the first.
+    pub collapse_escapes: bool,
+    /// Treats a leading `[[` as a literal `[` instead of a language specification.
+    pub doubled_delimiter_escape: bool,
+    /// Base class of highlighted inline code, `hljs` if absent and none if empty.
+    pub base_class: Option<String>,
+    /// Languages whose generated element omits the base `hljs` class.
+    pub no_base_class_for: Vec<String>,
+    /// Base classes replacing `hljs` for some languages, e.g. highlighted by
+    /// another library.
+    pub base_class_for: BTreeMap<String, String>,
+    /// Case of the base `hljs` class.
+    pub base_class_case: Case,
+    /// Whether the language is marked by a `language-*` class, a `data-language`
+    /// attribute or both.
+    pub language_marking: LanguageMarking,
+    /// Adds the language as `data-lang` attribute to highlighted inline code.
+    pub data_lang: bool,
+    /// Attribute set to `true` on highlighted inline code for copy buttons, e.g.
+    /// `data-copy`.
+    pub copy_button_attr: Option<String>,
+    /// Adds the inline code as written, including its language specification, as
+    /// `title` attribute.
+    pub original_as_title: bool,
+    /// Adds the language as written, before resolving aliases, as
+    /// `data-original-lang` attribute.
+    pub record_original_language: bool,
+    /// Replaces each tab of highlighted inline code.
+    pub tab_replacement: Option<String>,
+    /// Characters removed from the end of highlighted inline code, e.g. `;,`.
+    pub strip_trailing_punct: Option<String>,
+    /// Wraps each whitespace-delimited token of highlighted inline code in a
+    /// `<span class="tok">`, e.g. for animations.
+    #[cfg(feature = "token-wrap")]
+    pub token_wrap: bool,
+    /// Keeps browsers from collapsing whitespace in highlighted inline code.
+    pub preserve_whitespace: bool,
+    /// Adds `dir="ltr"` to generated elements so code stays left-to-right in
+    /// right-to-left books.
+    pub force_ltr: bool,
+    /// Adds a `nonce` attribute with this value to generated elements for a
+    /// Content-Security-Policy.
+    pub nonce: Option<String>,
+    /// Class used by `preserve-whitespace` instead of a `white-space: pre` style.
+    pub preserve_whitespace_class: Option<String>,
+    /// Class of a `<span>` wrapping the code inside its element, e.g. to keep
+    /// whitespace where the style of the element cannot be relied on.
+    pub inner_wrapper_class: Option<String>,
+    /// Adds the line of highlighted inline code in its chapter as `data-source-line`
+    /// attribute.
+    pub source_position_attrs: bool,
+    /// Wraps highlighted inline code in `<data value="LANGUAGE">`.
+    pub data_element: bool,
+    /// Wraps highlighted inline code together with a plain `<noscript>` version.
+    pub noscript_fallback: bool,
+    /// Adds a hidden `<span class="clipboard-src">` with the escaped code after
+    /// highlighted inline code, e.g. for copy buttons.
+    pub clipboard_source: bool,
+    /// Adds a `<sup class="lang-badge">` with the language after highlighted inline
+    /// code, except auto-detected code.
+    pub language_badge: bool,
+    /// Class of auto-detected (`[auto]`) inline code, `hljs` if absent. Read from
+    /// `auto-detect-class` or its older name `auto-class`.
+    pub auto_class: Option<String>,
+    /// Writes fenced code blocks with their original fence character (`~` or `` ` ``)
+    /// instead of always using backticks.
+    pub preserve_fence_char: bool,
+    /// Writes emphasis with its original character (`_` or `*`) instead of always
+    /// using asterisks.
+    pub preserve_emphasis_char: bool,
+    /// Writes Setext headings (underlined with `===` or `---`) as such instead of
+    /// converting them to ATX headings (prefixed with `#`).
+    pub preserve_heading_style: bool,
+    /// Emits plain inline code with a class derived from the book's theme, e.g.
+    /// `plain-navy`.
+    pub theme_aware_plain: bool,
+    /// Mirrors `output.html.default-theme`.
+    pub html_theme: Option<String>,
+    /// Mirrors `output.html.smart-punctuation` so the parser matches the renderer.
+    pub smart_punctuation: bool,
+    /// Parses `$...$` and `$$...$$` as math, which is left as it is, including
+    /// inline code inside of it.
+    pub math: bool,
+    /// Reads the `+++` TOML front matter of chapters, whose `[inline-highlighting]`
+    /// table overrides the configuration, and removes it from them.
+    pub front_matter: bool,
+    /// Leaves draft chapters, which have no path, as they are.
+    pub skip_drafts: bool,
+    /// Merges inline codes with the same language separated by nothing but
+    /// whitespace into one element.
+    pub merge_adjacent: bool,
+    /// Titles of the headings whose sections, including their subsections, are
+    /// highlighted if not empty. Other inline code is left as it is.
+    pub sections: Vec<String>,
+    /// Reuses chapters highlighted by an earlier run with the same content and
+    /// configuration, which are cached in the build directory of the book.
+    pub enable_cache: bool,
+    /// Logs chapters without any highlighted inline code although a default
+    /// language applies.
+    pub warn_unused_default: bool,
+    /// Logs generated HTML that is not a single well-formed element.
+    pub validate_html: bool,
+    /// Logs inline code at least this many bytes long making up a whole paragraph,
+    /// which may be meant as fenced code block.
+    pub detect_misfenced: Option<usize>,
+    /// Leaves inline code found by `detect-misfenced` as it is.
+    pub misfenced_plain: bool,
+    /// Logs chapters larger than this many bytes after highlighting.
+    pub warn_on_large_output: Option<usize>,
+    /// Removes a byte order mark and converts all line endings to LF in the output.
+    pub normalize_output: bool,
+    /// Initial capacity of the buffer a chapter is serialized into as a multiple of
+    /// its length, to save reallocations for large chapters.
+    pub serialize_buffer_capacity: Option<f64>,
+    /// Appends a chapter listing how often each chapter uses each language.
+    pub generate_stats_chapter: bool,
+    /// Title of the statistics chapter, `Language Statistics` if absent.
+    pub stats_chapter_title: Option<String>,
+    /// Path of the statistics chapter, `language-statistics.md` if absent.
+    pub stats_chapter_path: Option<String>,
+    /// Logs the distinct languages used in the book as JSON array.
+    pub report_languages: bool,
+    /// Logs the effective configuration when the preprocessor runs.
+    pub dump_config: bool,
+    /// Silences every message of this preprocessor.
+    pub no_logging: bool,
 }
 
 impl Configuration {
-    pub(crate) fn from_mdbook_config(cfg: &mdbook_preprocessor::config::Config) -> Self {
-        let default_language = cfg
-            .get::<String>("preprocessor.inline-highlighting.default-language")
-            .ok()
-            .flatten();
+    /// Reads the configuration from `book.toml`. Values of the wrong type are ignored,
+    /// unless `config-strictness` is `strict`, which makes them an error.
+    pub fn from_mdbook_config(cfg: &Config) -> Result<Self> {
+        let mut table = Table {
+            cfg,
+            mismatches: RefCell::default(),
+            preset: toml::Table::new(),
+        };
+        let preset: Option<String> = table.get("preset");
+        if let Some(name) = &preset {
+            match PRESETS.iter().find(|(preset, _)| preset == name) {
+                Some((_, defaults)) => table.preset = defaults.parse().unwrap_or_default(),
+                None => table
+                    .mismatches
+                    .borrow_mut()
+                    .push(format!("`preset`: unknown preset `{name}`")),
+            }
+        }
+        let mut config = Configuration {
+            preset,
+            config_strictness: table.get("config-strictness").unwrap_or_default(),
+            default_language: table.get("default-language"),
+            comment_spec: table.get("comment-spec").unwrap_or(false),
+            syntax: table.get("syntax").unwrap_or_default(),
+            sigil: table.get("sigil"),
+            highlight_unmarked: table.get("highlight-unmarked"),
+            code_pattern: None,
+            aliases: table.get("aliases").unwrap_or_default(),
+            language_separators: table.get("language-separators").unwrap_or_default(),
+            separator_class: table.get("separator-class").unwrap_or_default(),
+            mark_language: table.get("mark-language"),
+            element: table.get("element"),
+            auto_element: table.get("auto-element"),
+            target: table.get("target").unwrap_or_default(),
+            emission: table.get("emission").unwrap_or_default(),
+            element_map: table.get("element-map").unwrap_or_default(),
+            language_groups: table.get("language-groups").unwrap_or_default(),
+            language_affixes: table.get("language-affixes").unwrap_or_default(),
+            always_class: table.get("always-class"),
+            dual_theme_classes: table.get("dual-theme-classes").unwrap_or(false),
+            theme_classes: table.get("theme-classes").unwrap_or_default(),
+            final_class_map: table.get("final-class-map").unwrap_or_default(),
+            doc_links: table.get("doc-links").unwrap_or_default(),
+            css_var_map: table.get("css-var-map").unwrap_or_default(),
+            normalize_language: table.get("normalize-language").unwrap_or(false),
+            known_languages: table.get("known-languages").unwrap_or_default(),
+            allowed_languages: table.get("allowed-languages").unwrap_or_default(),
+            bare_language: table.get("bare-language").unwrap_or(false),
+            empty_code_policy: table.get("empty-code-policy").unwrap_or_default(),
+            require_separator: table.get("require-separator"),
+            body_strip_prefix: table.get("body-strip-prefix"),
+            body_strip_suffix: table.get("body-strip-suffix"),
+            min_language_length: table.get("min-language-length").unwrap_or(0),
+            collapse_escapes: table.get("collapse-escapes").unwrap_or(false),
+            doubled_delimiter_escape: table.get("doubled-delimiter-escape").unwrap_or(false),
+            base_class: table.get("base-class"),
+            no_base_class_for: table.get("no-base-class-for").unwrap_or_default(),
+            base_class_for: table.get("base-class-for").unwrap_or_default(),
+            base_class_case: table.get("base-class-case").unwrap_or_default(),
+            language_marking: table.get("language-marking").unwrap_or_default(),
+            data_lang: table.get("data-lang").unwrap_or(false),
+            copy_button_attr: table.get("copy-button-attr"),
+            original_as_title: table.get("original-as-title").unwrap_or(false),
+            record_original_language: table.get("record-original-language").unwrap_or(false),
+            tab_replacement: table.get("tab-replacement"),
+            strip_trailing_punct: table.get("strip-trailing-punct"),
+            #[cfg(feature = "token-wrap")]
+            token_wrap: table.get("token-wrap").unwrap_or(false),
+            preserve_whitespace: table.get("preserve-whitespace").unwrap_or(false),
+            force_ltr: table.get("force-ltr").unwrap_or(false),
+            nonce: table.get("nonce"),
+            preserve_whitespace_class: table.get("preserve-whitespace-class"),
+            inner_wrapper_class: table.get("inner-wrapper-class"),
+            source_position_attrs: table.get("source-position-attrs").unwrap_or(false),
+            data_element: table.get("data-element").unwrap_or(false),
+            noscript_fallback: table.get("noscript-fallback").unwrap_or(false),
+            clipboard_source: table.get("clipboard-source").unwrap_or(false),
+            language_badge: table.get("language-badge").unwrap_or(false),
+            auto_class: table
+                .get("auto-detect-class")
+                .or_else(|| table.get("auto-class")),
+            preserve_fence_char: table.get("preserve-fence-char").unwrap_or(false),
+            preserve_emphasis_char: table.get("preserve-emphasis-char").unwrap_or(false),
+            preserve_heading_style: table.get("preserve-heading-style").unwrap_or(false),
+            theme_aware_plain: table.get("theme-aware-plain").unwrap_or(false),
+            html_theme: cfg.get("output.html.default-theme").ok().flatten(),
+            smart_punctuation: cfg
+                .get("output.html.smart-punctuation")
+                .ok()
+                .flatten()
+                .unwrap_or(false),
+            math: table.get("math").unwrap_or(false),
+            front_matter: table.get("front-matter").unwrap_or(false),
+            skip_drafts: table.get("skip-drafts").unwrap_or(false),
+            merge_adjacent: table.get("merge-adjacent").unwrap_or(false),
+            sections: table.get("sections").unwrap_or_default(),
+            enable_cache: table.get("enable-cache").unwrap_or(false),
+            warn_unused_default: table.get("warn-unused-default").unwrap_or(false),
+            validate_html: table.get("validate-html").unwrap_or(false),
+            detect_misfenced: table.get("detect-misfenced"),
+            misfenced_plain: table.get("misfenced-plain").unwrap_or(false),
+            warn_on_large_output: table.get("warn-on-large-output"),
+            normalize_output: table.get("normalize-output").unwrap_or(false),
+            serialize_buffer_capacity: table.get("serialize-buffer-capacity"),
+            generate_stats_chapter: table.get("generate-stats-chapter").unwrap_or(false),
+            stats_chapter_title: table.get("stats-chapter-title"),
+            stats_chapter_path: table.get("stats-chapter-path"),
+            report_languages: table.get("report-languages").unwrap_or(false),
+            dump_config: table.get("dump-config").unwrap_or(false),
+            no_logging: table.get("no-logging").unwrap_or(false),
+        };
+        config.aliases = config.flatten_aliases();
+        config.warn_unknown_alias_targets();
+        config.language_groups = config.known_language_groups();
+        config.element_map = config.valid_elements();
+        let element = config.element.take();
+        config.element = config.valid_element("element", element);
+        let auto_element = config.auto_element.take();
+        config.auto_element = config.valid_element("auto-element", auto_element);
+        let copy_button_attr = config.copy_button_attr.take();
+        config.copy_button_attr = copy_button_attr.filter(|name| {
+            let valid = is_attribute_name(name);
+            if !valid {
+                logging::log(
+                    &config,
+                    Level::Warn,
+                    format_args!(
+                        "ignoring invalid attribute `{}` of `copy-button-attr`",
+                        name
+                    ),
+                );
+            }
+            valid
+        });
+        config.code_pattern = table.get::<String>("code-pattern").and_then(|pattern| {
+            Regex::new(&pattern)
+                .inspect_err(|error| {
+                    logging::log(
+                        &config,
+                        Level::Error,
+                        format_args!("invalid `code-pattern`: {}", error),
+                    )
+                })
+                .ok()
+        });
+        if config.config_strictness == Strictness::Strict {
+            let mismatches = table.mismatches.into_inner();
+            if !mismatches.is_empty() {
+                return Err(Error::msg(format!(
+                    "invalid configuration of this preprocessor: {}",
+                    mismatches.join("; ")
+                )));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Removes aliases which are or lead into a cycle and resolves aliases pointing to
+    /// other aliases to the language at the end of the chain, warning about each.
+    fn flatten_aliases(&self) -> BTreeMap<String, String> {
+        let mut aliases = BTreeMap::new();
+        for (alias, target) in &self.aliases {
+            let mut visited = vec![alias];
+            let mut resolved = target;
+            while let Some(next) = self.aliases.get(resolved) {
+                if visited.contains(&resolved) {
+                    break;
+                }
+                visited.push(resolved);
+                resolved = next;
+            }
+            if self.aliases.contains_key(resolved) {
+                logging::log(
+                    self,
+                    Level::Warn,
+                    format_args!("ignoring cyclic alias `{}` = `{}`", alias, target),
+                );
+                continue;
+            }
+            if resolved != target {
+                logging::log(
+                    self,
+                    Level::Warn,
+                    format_args!(
+                        "alias `{}` points to alias `{}`, resolving it to `{}`",
+                        alias, target, resolved
+                    ),
+                );
+            }
+            aliases.insert(alias.clone(), resolved.clone());
+        }
+        aliases
+    }
+
+    /// Warns about aliases resolving to a language outside of `known-languages`, if
+    /// there are known languages.
+    fn warn_unknown_alias_targets(&self) {
+        if self.known_languages.is_empty() {
+            return;
+        }
+        for (alias, target) in &self.aliases {
+            if !self.is_known_language(target) {
+                logging::log(
+                    self,
+                    Level::Warn,
+                    format_args!(
+                        "alias `{}` points to `{}`, which is not a known language",
+                        alias, target
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Removes language groups outside of [`LANGUAGE_GROUPS`], warning about each.
+    fn known_language_groups(&self) -> BTreeMap<String, String> {
+        let mut groups = self.language_groups.clone();
+        groups.retain(|language, group| {
+            let known = LANGUAGE_GROUPS.contains(&group.as_str());
+            if !known {
+                logging::log(
+                    self,
+                    Level::Warn,
+                    format_args!(
+                        "ignoring unknown group `{}` of language `{}`, expected one of {:?}",
+                        group, language, LANGUAGE_GROUPS
+                    ),
+                );
+            }
+            known
+        });
+        groups
+    }
+
+    /// Removes pseudo-languages mapped to something other than an element name,
+    /// warning about each.
+    fn valid_elements(&self) -> BTreeMap<String, String> {
+        let mut elements = self.element_map.clone();
+        elements.retain(|language, element| {
+            let valid = element.is_empty() || is_element_name(element);
+            if !valid {
+                logging::log(
+                    self,
+                    Level::Warn,
+                    format_args!(
+                        "ignoring invalid element `{}` of pseudo-language `{}`",
+                        element, language
+                    ),
+                );
+            }
+            valid
+        });
+        elements
+    }
+
+    /// Returns `element` unless it is no element name, warning about it then.
+    fn valid_element(&self, key: &str, element: Option<String>) -> Option<String> {
+        element.filter(|name| {
+            let valid = is_element_name(name);
+            if !valid {
+                logging::log(
+                    self,
+                    Level::Warn,
+                    format_args!("ignoring invalid element `{}` of `{}`", name, key),
+                );
+            }
+            valid
+        })
+    }
+
+    pub fn separator_for(&self, language: &str) -> &str {
+        self.language_separators
+            .get(language)
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_SEPARATOR)
+    }
+
+    /// Strips the separator of `language` off `rest`, returning `None` if it is
+    /// missing.
+    pub fn strip_separator<'a>(&self, language: &str, rest: &'a str) -> Option<&'a str> {
+        match self.language_separators.get(language) {
+            Some(separator) => rest.strip_prefix(separator.as_str()),
+            None => rest.strip_prefix(|ch| self.separator_class.matches(ch)),
+        }
+    }
+
+    /// Returns the element of the pseudo-language `language` of `element-map`, if it
+    /// is one.
+    pub fn element_for(&self, language: &str) -> Option<&str> {
+        match self.element_map.get(language) {
+            Some(element) => Some(element.as_str()).filter(|element| !element.is_empty()),
+            None => DEFAULT_ELEMENT_MAP
+                .iter()
+                .find(|(pseudo, _)| *pseudo == language)
+                .map(|(_, element)| *element),
+        }
+    }
+
+    /// Returns the pseudo-language of `mark-language`, if any.
+    pub fn mark_language(&self) -> Option<&str> {
+        match self.mark_language.as_deref() {
+            None => Some(DEFAULT_MARK_LANGUAGE),
+            Some("") => None,
+            language => language,
+        }
+    }
+
+    pub fn is_known_language(&self, language: &str) -> bool {
+        self.known_languages.iter().any(|l| l == language)
+    }
+
+    /// Returns the canonical name of `language`, following the configured aliases.
+    pub fn resolve_language<'a>(&'a self, language: &'a str) -> &'a str {
+        self.aliases
+            .get(language)
+            .map(String::as_str)
+            .unwrap_or(language)
+    }
+}
+
+/// Returns every recognized configuration key with its default value, which is
+/// empty if the key is unset by default.
+pub fn config_keys() -> &'static [(&'static str, &'static str)] {
+    CONFIG_KEYS
+}
+
+/// Overrides from the `[inline-highlighting]` table of a chapter's TOML front matter.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct ChapterOverrides {
+    pub default_language: Option<String>,
+    #[serde(default)]
+    pub disable: bool,
+}
+
+impl ChapterOverrides {
+    pub(crate) fn from_front_matter(front_matter: &str) -> Result<Self, toml::de::Error> {
+        #[derive(Deserialize)]
+        struct FrontMatter {
+            #[serde(default, rename = "inline-highlighting")]
+            overrides: ChapterOverrides,
+        }
+
+        toml::from_str::<FrontMatter>(front_matter).map(|f| f.overrides)
+    }
+}
+
+/// Literal text around the code of a language in `language-affixes`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+pub struct Affixes {
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Syntax {
+    /// `[LANGUAGE] CODE`
+    #[default]
+    Brackets,
+    /// `#LANGUAGE CODE`, with a configurable sigil.
+    Sigil,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Target {
+    /// `<code class="hljs language-LANGUAGE">CODE</code>`
+    #[default]
+    Html,
+    /// `<Code lang="LANGUAGE">{`CODE`}</Code>`, e.g. for MDX.
+    Jsx,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Emission {
+    /// The markup as raw HTML.
+    #[default]
+    RawHtml,
+    /// Inline code containing the markup as `data:text/html,` URI, e.g. for
+    /// renderers rejecting raw HTML with a script expanding these.
+    DataUri,
+    /// The markup with its angle brackets encoded as entities, e.g. for backends
+    /// accepting little inline HTML.
+    EntityEncode,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strictness {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Case {
+    Lower,
+    Upper,
+    #[default]
+    AsIs,
+}
+
+impl Case {
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            Case::Lower => text.to_lowercase(),
+            Case::Upper => text.to_uppercase(),
+            Case::AsIs => text.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SeparatorClass {
+    /// A space only.
+    #[default]
+    Space,
+    /// ASCII whitespace, e.g. a space or a tab.
+    Ascii,
+    /// Any Unicode whitespace, e.g. a non-breaking space.
+    Unicode,
+}
+
+impl SeparatorClass {
+    pub fn matches(self, ch: char) -> bool {
+        match self {
+            SeparatorClass::Space => ch == ' ',
+            SeparatorClass::Ascii => ch.is_ascii_whitespace(),
+            SeparatorClass::Unicode => ch.is_whitespace(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmptyCodePolicy {
+    /// An empty highlighted element.
+    #[default]
+    EmitEmpty,
+    /// Nothing.
+    Skip,
+    /// Empty plain inline code.
+    Plain,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LanguageMarking {
+    #[default]
+    Class,
+    Attribute,
+    Both,
+}
+
+impl LanguageMarking {
+    pub fn class(self) -> bool {
+        matches!(self, LanguageMarking::Class | LanguageMarking::Both)
+    }
+
+    pub fn attribute(self) -> bool {
+        matches!(self, LanguageMarking::Attribute | LanguageMarking::Both)
+    }
+}
+
+fn is_element_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|ch| ch.is_ascii_alphanumeric())
+}
+
+fn is_attribute_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '-')
+}
+
+/// This preprocessor's table of the mdBook configuration.
+struct Table<'a> {
+    cfg: &'a Config,
+    /// Descriptions of the values of the wrong type read so far.
+    mismatches: RefCell<Vec<String>>,
+    /// Defaults of the selected preset for absent keys.
+    preset: toml::Table,
+}
+
+impl Table<'_> {
+    /// Reads the value of `key`, or the default of the preset if absent, ignoring
+    /// but recording values of the wrong type.
+    fn get<'de, T: Deserialize<'de>>(&self, key: &str) -> Option<T> {
+        self.cfg
+            .get(&format!("{CONFIG_PREFIX}.{key}"))
+            .unwrap_or_else(|error| {
+                self.mismatches
+                    .borrow_mut()
+                    .push(format!("`{key}`: {error:#}"));
+                None
+            })
+            .or_else(|| self.preset.get(key)?.clone().try_into().ok())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dump_contains_default_language() {
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting]
+            default-language = "javascript"
+            dump-config = true
+        "#
+        .parse()
+        .unwrap();
+        let config = Configuration::from_mdbook_config(&cfg).unwrap();
+        assert!(config.dump_config);
+        assert!(format!("{config:?}").contains(r#"default_language: Some("javascript")"#));
+    }
+
+    #[test]
+    fn chained_and_cyclic_aliases() {
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting.aliases]
+            js = "ecmascript"
+            ecmascript = "javascript"
+            self = "self"
+            ping = "pong"
+            pong = "ping"
+        "#
+        .parse()
+        .unwrap();
+        let mut config = None;
+        let records = logging::capture::logged(|| {
+            config = Some(Configuration::from_mdbook_config(&cfg).unwrap());
+        });
+        let aliases = config.unwrap().aliases;
+        assert_eq!(
+            BTreeMap::from([
+                ("ecmascript".to_string(), "javascript".to_string()),
+                ("js".to_string(), "javascript".to_string()),
+            ]),
+            aliases
+        );
+        assert_eq!(4, records.len());
+        assert!(records.iter().all(|(level, _)| *level == Level::Warn));
+
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting.aliases]
+            a = "b"
+            b = "c"
+            c = "d"
+            x = "y"
+            y = "z"
+            z = "x"
+            w = "x"
+        "#
+        .parse()
+        .unwrap();
+        let mut config = None;
+        let records = logging::capture::logged(|| {
+            config = Some(Configuration::from_mdbook_config(&cfg).unwrap());
+        });
+        assert_eq!(
+            BTreeMap::from([
+                ("a".to_string(), "d".to_string()),
+                ("b".to_string(), "d".to_string()),
+                ("c".to_string(), "d".to_string()),
+            ]),
+            config.unwrap().aliases
+        );
+        let cyclic = records
+            .iter()
+            .filter(|(_, message)| message.starts_with("ignoring cyclic alias"))
+            .count();
+        assert_eq!(4, cyclic);
+        assert!(records.contains(&(
+            Level::Warn,
+            "alias `a` points to alias `b`, resolving it to `d`".to_string()
+        )));
+    }
+
+    #[test]
+    fn base_class_case_from_config() {
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting]
+            base-class-case = "upper"
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            Case::Upper,
+            Configuration::from_mdbook_config(&cfg)
+                .unwrap()
+                .base_class_case
+        );
+    }
+
+    #[test]
+    fn unknown_language_groups() {
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting.language-groups]
+            python = "scripting"
+            rust = "compiled"
+        "#
+        .parse()
+        .unwrap();
+        let mut config = None;
+        let records = logging::capture::logged(|| {
+            config = Some(Configuration::from_mdbook_config(&cfg).unwrap());
+        });
+        assert_eq!(
+            BTreeMap::from([("python".to_string(), "scripting".to_string())]),
+            config.unwrap().language_groups
+        );
+        assert_eq!(1, records.len());
+        assert!(records[0].1.contains("`compiled`"));
+    }
+
+    #[test]
+    fn config_keys_cover_fields() {
+        assert!(config_keys().contains(&("default-language", "")));
+        let fields = format!("{:#?}", Configuration::default());
+        let fields = fields
+            .lines()
+            .filter_map(|line| line.strip_prefix("    ")?.split_once(": ").map(|(f, _)| f))
+            // Read from mdBook's own `output.html` table.
+            .filter(|&field| !["smart_punctuation", "html_theme"].contains(&field));
+        let keys: Vec<_> = config_keys().iter().map(|(key, _)| *key).collect();
+        for field in fields {
+            assert!(keys.contains(&field.replace('_', "-").as_str()), "{field}");
+        }
+    }
+
+    #[test]
+    fn invalid_copy_button_attr() {
+        let config = |name: &str| {
+            let cfg: Config =
+                format!("[preprocessor.inline-highlighting]\ncopy-button-attr = '{name}'")
+                    .parse()
+                    .unwrap();
+            Configuration::from_mdbook_config(&cfg)
+                .unwrap()
+                .copy_button_attr
+        };
+        assert_eq!(Some("data-copy".to_string()), config("data-copy"));
+        assert_eq!(None, config("x onclick=y"));
+    }
+
+    #[test]
+    fn presets() {
+        let config = |toml: &str| {
+            let cfg: Config = format!("[preprocessor.inline-highlighting]\n{toml}")
+                .parse()
+                .unwrap();
+            Configuration::from_mdbook_config(&cfg).unwrap()
+        };
+        let highlight = |config: &Configuration| crate::highlight_inline("`[rust] x`", config);
+        assert_eq!(
+            "<code class=\"hljs language-rust\" data-language=\"rust\">x</code>",
+            highlight(&config("preset = \"highlightjs\""))
+        );
+        assert_eq!(
+            "<code class=\"language-rust\">x</code>",
+            highlight(&config("preset = \"prism\""))
+        );
+        assert_eq!(
+            "<code>x</code>",
+            crate::highlight_inline("`[auto] x`", &config("preset = \"prism\""))
+        );
+        let accessible = config("preset = \"accessible\"");
+        assert!(accessible.original_as_title);
+        assert!(accessible.noscript_fallback);
+        assert!(accessible.language_badge);
+        assert!(accessible.force_ltr);
+        assert!(highlight(&accessible).contains(r#"<noscript><code>x</code></noscript>"#));
+        // Keys of the table override the preset.
+        let overridden = config("preset = \"accessible\"\nlanguage-badge = false");
+        assert!(!overridden.language_badge);
+        assert!(overridden.force_ltr);
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting]
+            preset = "unknown"
+            config-strictness = "strict"
+        "#
+        .parse()
+        .unwrap();
+        assert!(Configuration::from_mdbook_config(&cfg).is_err());
+    }
+
+    #[test]
+    fn auto_detect_class_from_config() {
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting]
+            auto-detect-class = "hljs detected"
+            auto-class = "hljs auto"
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            Some("hljs detected"),
+            Configuration::from_mdbook_config(&cfg)
+                .unwrap()
+                .auto_class
+                .as_deref()
+        );
+    }
+
+    #[test]
+    fn config_strictness() {
+        let wrong_type = |strictness| {
+            format!(
+                r#"
+                [preprocessor.inline-highlighting]
+                config-strictness = "{strictness}"
+                data-lang = "yes"
+                "#
+            )
+            .parse::<Config>()
+            .unwrap()
+        };
+        let config = Configuration::from_mdbook_config(&wrong_type("lenient")).unwrap();
+        assert!(!config.data_lang);
+        let error = Configuration::from_mdbook_config(&wrong_type("strict")).unwrap_err();
+        assert!(error.to_string().contains("`data-lang`"), "{error}");
+    }
+
+    #[test]
+    fn language_affixes_from_config() {
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting.language-affixes]
+            pycon = { prefix = ">>> " }
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            Some(&Affixes {
+                prefix: ">>> ".to_string(),
+                suffix: String::new(),
+            }),
+            Configuration::from_mdbook_config(&cfg)
+                .unwrap()
+                .language_affixes
+                .get("pycon")
+        );
+    }
+
+    #[test]
+    fn invalid_elements() {
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting.element-map]
+            var = "var"
+            kbd = "kbd onclick"
+        "#
+        .parse()
+        .unwrap();
+        let mut config = None;
+        let records = logging::capture::logged(|| {
+            config = Some(Configuration::from_mdbook_config(&cfg).unwrap());
+        });
+        assert_eq!(
+            BTreeMap::from([("var".to_string(), "var".to_string())]),
+            config.unwrap().element_map
+        );
+        assert_eq!(1, records.len());
+    }
+
+    #[test]
+    fn html_theme_from_config() {
+        let cfg: Config = r#"
+            [output.html]
+            default-theme = "coal"
+        "#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            Some("coal"),
+            Configuration::from_mdbook_config(&cfg)
+                .unwrap()
+                .html_theme
+                .as_deref()
+        );
+    }
 
-        Configuration { default_language }
+    #[test]
+    fn unknown_alias_targets() {
+        let cfg: Config = r#"
+            [preprocessor.inline-highlighting]
+            known-languages = ["javascript"]
+            [preprocessor.inline-highlighting.aliases]
+            js = "javascript"
+            py = "pyhton"
+        "#
+        .parse()
+        .unwrap();
+        let records = logging::capture::logged(|| {
+            Configuration::from_mdbook_config(&cfg).unwrap();
+        });
+        assert_eq!(1, records.len());
+        assert!(records[0].1.contains("`py` points to `pyhton`"));
     }
 }
@@ -0,0 +1,57 @@
+use std::fs;
+use std::path::Path;
+
+use mdbook_preprocessor::book::Chapter;
+use mdbook_preprocessor::errors::Result;
+
+use crate::config::Configuration;
+use crate::preprocessor::highlight_chapter;
+
+/// Highlights the inline codes of every `.md` file in `dir` and its subdirectories,
+/// rewriting the files in place.
+pub fn process_path(dir: &Path, cfg: &Configuration) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            process_path(&path, cfg)?;
+        } else if path.extension().is_some_and(|ext| ext == "md") {
+            let mut chapter = Chapter::new(
+                &path.display().to_string(),
+                fs::read_to_string(&path)?,
+                &path,
+                vec![],
+            );
+            highlight_chapter(&mut chapter, cfg);
+            fs::write(&path, chapter.content)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rewrites_markdown_files() {
+        let dir =
+            std::env::temp_dir().join(format!("mdbook-inline-highlighting-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("a.md"), "`[rust] x`").unwrap();
+        fs::write(dir.join("nested/b.md"), "`[py] y`").unwrap();
+        fs::write(dir.join("c.txt"), "`[rust] x`").unwrap();
+
+        process_path(&dir, &Configuration::default()).unwrap();
+
+        assert_eq!(
+            "<code class=\"hljs language-rust\">x</code>",
+            fs::read_to_string(dir.join("a.md")).unwrap()
+        );
+        assert_eq!(
+            "<code class=\"hljs language-py\">y</code>",
+            fs::read_to_string(dir.join("nested/b.md")).unwrap()
+        );
+        assert_eq!("`[rust] x`", fs::read_to_string(dir.join("c.txt")).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
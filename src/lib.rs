@@ -0,0 +1,36 @@
+mod cache;
+pub mod config;
+#[cfg(feature = "fs")]
+mod fs;
+mod logging;
+mod preprocessor;
+
+pub use config::{Configuration, config_keys};
+#[cfg(feature = "fs")]
+pub use fs::process_path;
+pub use logging::Diagnostic;
+pub use preprocessor::{
+    ConfiguredInlineHighlighter, InlineHighlighterPreprocessor, canonicalize, highlight_inline,
+    highlight_inline_with_diagnostics, process_book,
+};
+
+#[cfg(test)]
+mod test {
+    /// The preview chapter of the test book includes the example of the README by
+    /// line numbers, which move with any edit of the README above the example.
+    #[test]
+    fn preview_includes_readme_example() {
+        let preview = include_str!("../test_book/src/preview.md");
+        let readme = include_str!("../README.md");
+        let (range, _) = preview
+            .split_once("README.md:")
+            .and_then(|(_, rest)| rest.split_once("}}"))
+            .unwrap();
+        let (start, end) = range.split_once(':').unwrap();
+        let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+        let lines: Vec<_> = readme.lines().collect();
+        let (_, example) = readme.split_once("```markdown\n").unwrap();
+        let (example, _) = example.split_once("\n```").unwrap();
+        assert_eq!(example, lines[start - 1..end].join("\n"));
+    }
+}
@@ -0,0 +1,198 @@
+use std::cell::RefCell;
+use std::fmt::Arguments;
+
+use log::Level;
+
+use crate::config::Configuration;
+
+/// A message about an issue found while highlighting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+}
+
+thread_local! {
+    static DIAGNOSTICS: RefCell<Option<Vec<Diagnostic>>> = const { RefCell::new(None) };
+    static DEFERRED: RefCell<Option<Vec<Diagnostic>>> = const { RefCell::new(None) };
+}
+
+/// Every message of this crate goes through here so `no-logging` can silence them.
+/// Messages are also recorded as diagnostics while [`collect`] runs on this thread.
+pub(crate) fn log(config: &Configuration, level: Level, args: Arguments<'_>) {
+    DIAGNOSTICS.with_borrow_mut(|diagnostics| {
+        if let Some(diagnostics) = diagnostics {
+            diagnostics.push(Diagnostic {
+                level,
+                message: args.to_string(),
+            });
+        }
+    });
+    let deferred = DEFERRED.with_borrow_mut(|deferred| match deferred {
+        Some(deferred) => {
+            deferred.push(Diagnostic {
+                level,
+                message: args.to_string(),
+            });
+            true
+        }
+        None => false,
+    });
+    if deferred {
+        return;
+    }
+    if !config.no_logging {
+        log::log!(level, "{}", args);
+    }
+}
+
+/// Runs `f` and returns its result together with the diagnostics it logged, which
+/// an enclosing `collect` records as well.
+pub(crate) fn collect<T>(f: impl FnOnce() -> T) -> (T, Vec<Diagnostic>) {
+    let outer = DIAGNOSTICS.replace(Some(vec![]));
+    let result = f();
+    let diagnostics = DIAGNOSTICS.replace(outer).unwrap_or_default();
+    DIAGNOSTICS.with_borrow_mut(|outer| {
+        if let Some(outer) = outer {
+            outer.extend(diagnostics.iter().cloned());
+        }
+    });
+    (result, diagnostics)
+}
+
+/// Runs `f`, holding back the messages it logs on this thread for [`replay`], e.g.
+/// to log the messages of parallel work in a deterministic order.
+#[cfg(feature = "rayon")]
+pub(crate) fn defer<T>(f: impl FnOnce() -> T) -> (T, Vec<Diagnostic>) {
+    let outer = DEFERRED.replace(Some(vec![]));
+    let result = f();
+    let deferred = DEFERRED.replace(outer).unwrap_or_default();
+    (result, deferred)
+}
+
+/// Logs `diagnostics` again, e.g. the messages held back by [`defer`] or those of a
+/// cached chapter.
+pub(crate) fn replay(config: &Configuration, diagnostics: Vec<Diagnostic>) {
+    for diagnostic in diagnostics {
+        log(
+            config,
+            diagnostic.level,
+            format_args!("{}", diagnostic.message),
+        );
+    }
+}
+
+/// A logger recording the messages of the current thread so tests can inspect them.
+#[cfg(test)]
+pub(crate) mod capture {
+    use std::cell::RefCell;
+    use std::sync::Once;
+
+    use log::{Level, Log, Metadata, Record};
+
+    thread_local! {
+        static RECORDS: RefCell<Vec<(Level, String)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    struct CaptureLogger;
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            RECORDS.with_borrow_mut(|records| {
+                records.push((record.level(), record.args().to_string()))
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Runs `f` and returns the messages it logged.
+    pub(crate) fn logged(f: impl FnOnce()) -> Vec<(Level, String)> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CaptureLogger).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        RECORDS.with_borrow_mut(Vec::clear);
+        f();
+        RECORDS.with_borrow_mut(std::mem::take)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn logging_enabled() {
+        let config = Configuration::default();
+        let records = capture::logged(|| log(&config, Level::Warn, format_args!("hello")));
+        assert_eq!(vec![(Level::Warn, "hello".to_string())], records);
+    }
+
+    #[test]
+    fn logging_disabled() {
+        let config = Configuration {
+            no_logging: true,
+            ..Default::default()
+        };
+        let records = capture::logged(|| log(&config, Level::Warn, format_args!("hello")));
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn collect_diagnostics() {
+        let config = Configuration {
+            no_logging: true,
+            ..Default::default()
+        };
+        let ((), diagnostics) = collect(|| log(&config, Level::Warn, format_args!("hello")));
+        let expected = Diagnostic {
+            level: Level::Warn,
+            message: "hello".to_string(),
+        };
+        assert_eq!(vec![expected], diagnostics);
+        let ((), diagnostics) = collect(|| {});
+        assert!(diagnostics.is_empty());
+        let ((), outer) = collect(|| {
+            let ((), inner) = collect(|| log(&config, Level::Warn, format_args!("inner")));
+            assert_eq!(1, inner.len());
+        });
+        assert_eq!(1, outer.len());
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn collect_deferred() {
+        let config = Configuration::default();
+        let records = capture::logged(|| {
+            let (((), deferred), collected) =
+                collect(|| defer(|| log(&config, Level::Warn, format_args!("hello"))));
+            assert_eq!(deferred, collected);
+        });
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn defer_and_replay() {
+        let config = Configuration::default();
+        let records = capture::logged(|| {
+            let ((), deferred) = defer(|| log(&config, Level::Warn, format_args!("hello")));
+            assert_eq!(1, deferred.len());
+            log(&config, Level::Info, format_args!("first"));
+            replay(&config, deferred);
+        });
+        assert_eq!(
+            vec![
+                (Level::Info, "first".to_string()),
+                (Level::Warn, "hello".to_string())
+            ],
+            records
+        );
+    }
+}
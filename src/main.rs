@@ -1,12 +1,10 @@
-pub(crate) mod config;
-mod preprocessor;
-
 use std::io;
 use std::process;
 
 use clap::{Arg, ArgMatches, Command};
-use mdbook_preprocessor::errors::Error;
+use mdbook_inline_highlighting::InlineHighlighterPreprocessor;
 use mdbook_preprocessor::Preprocessor;
+use mdbook_preprocessor::errors::Error;
 use semver::{Version, VersionReq};
 
 fn cmd() -> Command {
@@ -25,7 +23,7 @@ fn main() {
     env_logger::init();
 
     let matches = cmd().get_matches();
-    let preproc = preprocessor::InlineHighlighterPreprocessor;
+    let preproc = InlineHighlighterPreprocessor;
 
     if let Some(sub_args) = matches.subcommand_matches("supports") {
         handle_supports(&preproc, sub_args);
@@ -1,106 +1,1041 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use log::Level;
 use mdbook_preprocessor::book::{Book, BookItem, Chapter};
-use mdbook_preprocessor::errors::Result;
+use mdbook_preprocessor::errors::{Error, Result};
 use mdbook_preprocessor::{Preprocessor, PreprocessorContext};
-use pulldown_cmark::{Event, Options, Parser};
-use pulldown_cmark_to_cmark::cmark;
+use pulldown_cmark::{
+    CodeBlockKind, Event, HeadingLevel, MetadataBlockKind, Options, Parser, Tag, TagEnd,
+};
+use pulldown_cmark_to_cmark::{
+    Error as CmarkError, Options as CmarkOptions, cmark_resume_with_options,
+};
 
-use crate::config::Configuration;
+use crate::cache::{CACHE_DIR, Cache};
+use crate::config::{
+    ChapterOverrides, Configuration, Emission, EmptyCodePolicy, Strictness, Syntax, Target,
+};
+use crate::logging::{self, Diagnostic};
 
 const ESCAPE_CHAR: char = '\\';
 const LANG_SPEC_START: char = '[';
 const LANG_SPEC_END: char = ']';
+const DESCRIBED_BY_PREFIX: &str = " #";
+const BOM: char = '\u{feff}';
+const DOC_LINK_CODE_PLACEHOLDER: &str = "{code}";
+const DATA_URI_PREFIX: &str = "data:text/html;charset=utf-8,";
+const DEFAULT_SIGIL: char = '#';
+const COMMENT_SPEC_START: &str = "<!--";
+const COMMENT_SPEC_END: &str = "-->";
+const BASE_CLASS: &str = "hljs";
+const DEFAULT_ELEMENT: &str = "code";
+const JSX_ELEMENT: &str = "Code";
+const AUTO_LANGUAGE: &str = "auto";
+const DIFF_LANGUAGE: &str = "diff";
+const DIFF_ADD_CLASS: &str = "diff-add";
+const DIFF_DEL_CLASS: &str = "diff-del";
+const LANG_DIRECTIVE: &str = "highlight-lang:";
+const PRESERVE_WHITESPACE_STYLE: &str = "white-space: pre;";
+const LANGUAGE_COLOR_PROPERTY: &str = "--lang-color";
+const NOSCRIPT_FALLBACK_CLASS: &str = "noscript-fallback";
+const CLIPBOARD_SOURCE_CLASS: &str = "clipboard-src";
+const LANGUAGE_BADGE_CLASS: &str = "lang-badge";
+const DEFAULT_THEME_CLASSES: [&str; 2] = ["hljs-light", "hljs-dark"];
+#[cfg(feature = "token-wrap")]
+const TOKEN_CLASS: &str = "tok";
+const PLAIN_THEME_CLASS_PREFIX: &str = "plain-";
+/// mdBook's theme if `output.html.default-theme` is absent.
+const DEFAULT_HTML_THEME: &str = "light";
+const DEFAULT_STATS_CHAPTER_TITLE: &str = "Language Statistics";
+const DEFAULT_STATS_CHAPTER_PATH: &str = "language-statistics.md";
+/// Environment variable turning the preprocessor off when set to `1`, e.g. for
+/// quick previews.
+const DISABLE_ENV: &str = "MDBOOK_INLINE_HIGHLIGHTING_DISABLE";
 
 #[derive(Default)]
-pub(crate) struct InlineHighlighterPreprocessor;
+pub struct InlineHighlighterPreprocessor;
 
 impl Preprocessor for InlineHighlighterPreprocessor {
     fn name(&self) -> &str {
         "inline-highlighting"
     }
 
-    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
-        let config = Configuration::from_mdbook_config(&ctx.config);
-        let default_language = config.default_language;
-
-        let smart_quotes = ctx
-            .config
-            .get::<bool>("output.html.smart-punctuation")
-            .ok()
-            .flatten()
-            .unwrap_or(false);
-
-        book.for_each_mut(|item: &mut BookItem| {
-            if let BookItem::Chapter(chapter) = item {
-                let mut buf = String::new();
-
-                let parser = new_cmark_parser(&chapter.content, smart_quotes);
-                let mut events = vec![];
-                for event in parser {
-                    events.push(if let Event::Code(code) = event {
-                        let (c, is_html) =
-                            parse_inline_code(code.as_ref(), default_language.as_deref(), chapter);
-                        if is_html {
-                            Event::Html(c.into())
-                        } else {
-                            Event::Code(c.into())
-                        }
-                    } else {
-                        event
+    fn run(&self, ctx: &PreprocessorContext, book: Book) -> Result<Book> {
+        if disabled_by_env() {
+            return Ok(book);
+        }
+        let config = Configuration::from_mdbook_config(&ctx.config)?;
+        run_with(&config, cache_for(&config, ctx).as_ref(), book)
+    }
+
+    fn supports_renderer(&self, renderer: &str) -> Result<bool> {
+        Ok(renderer == "html")
+    }
+}
+
+/// The preprocessor with a configuration of its own, ignoring the one of `book.toml`,
+/// for registering it programmatically.
+pub struct ConfiguredInlineHighlighter {
+    config: Configuration,
+}
+
+impl ConfiguredInlineHighlighter {
+    pub fn new(config: Configuration) -> Self {
+        ConfiguredInlineHighlighter { config }
+    }
+}
+
+impl Preprocessor for ConfiguredInlineHighlighter {
+    fn name(&self) -> &str {
+        InlineHighlighterPreprocessor.name()
+    }
+
+    fn run(&self, ctx: &PreprocessorContext, book: Book) -> Result<Book> {
+        if disabled_by_env() {
+            return Ok(book);
+        }
+        run_with(&self.config, cache_for(&self.config, ctx).as_ref(), book)
+    }
+
+    fn supports_renderer(&self, renderer: &str) -> Result<bool> {
+        InlineHighlighterPreprocessor.supports_renderer(renderer)
+    }
+}
+
+fn disabled_by_env() -> bool {
+    std::env::var_os(DISABLE_ENV).is_some_and(|value| value == "1")
+}
+
+/// Returns the cache in the build directory of the book if enabled.
+fn cache_for(config: &Configuration, ctx: &PreprocessorContext) -> Option<Cache> {
+    let dir = ctx.root.join(&ctx.config.build.build_dir).join(CACHE_DIR);
+    config.enable_cache.then(|| Cache::new(dir))
+}
+
+/// Highlights `book` like the preprocessor does with `config`, reusing the chapters
+/// of `cache` if given.
+fn run_with(config: &Configuration, cache: Option<&Cache>, mut book: Book) -> Result<Book> {
+    if config.dump_config {
+        logging::log(
+            config,
+            Level::Info,
+            format_args!("effective configuration: {:#?}", config),
+        );
+    }
+
+    let reports = highlight_items(&mut book.items, config, cache);
+    if let Some(cache) = cache {
+        cache.prune(config);
+    }
+    if config.generate_stats_chapter {
+        let title = config
+            .stats_chapter_title
+            .as_deref()
+            .unwrap_or(DEFAULT_STATS_CHAPTER_TITLE);
+        let path = config
+            .stats_chapter_path
+            .as_deref()
+            .unwrap_or(DEFAULT_STATS_CHAPTER_PATH);
+        book.push_item(Chapter::new(
+            title,
+            stats_chapter(title, &reports),
+            path,
+            vec![],
+        ));
+    }
+    if !config.allowed_languages.is_empty() {
+        let mut strays = vec![];
+        for report in &reports {
+            let languages = report.languages.keys();
+            for language in languages.filter(|language| !is_pseudo_language(language, config)) {
+                if !config.allowed_languages.contains(language) {
+                    logging::log(
+                        config,
+                        Level::Error,
+                        format_args!(
+                            "chapter `{}` uses `{}`, which is not an allowed language",
+                            report.chapter, language
+                        ),
+                    );
+                    strays.push(format!("`{}` in chapter `{}`", language, report.chapter));
+                }
+            }
+        }
+        if config.config_strictness == Strictness::Strict && !strays.is_empty() {
+            return Err(Error::msg(format!(
+                "languages outside of `allowed-languages` used: {}",
+                strays.join(", ")
+            )));
+        }
+    }
+    let mut languages = BTreeSet::new();
+    for report in reports {
+        languages.extend(report.languages.into_keys());
+    }
+    if config.report_languages {
+        languages.retain(|language| !is_pseudo_language(language, config));
+        logging::log(
+            config,
+            Level::Info,
+            format_args!("languages used: {}", serde_json::to_string(&languages)?),
+        );
+    }
+    Ok(book)
+}
+
+/// Returns whether `language` is auto-detection or a pseudo-language, neither of
+/// which highlight.js has to load.
+fn is_pseudo_language(language: &str, config: &Configuration) -> bool {
+    language == AUTO_LANGUAGE
+        || config.mark_language() == Some(language)
+        || config.element_for(language).is_some()
+}
+
+/// Returns the markdown of a chapter listing how often each chapter uses each
+/// language.
+fn stats_chapter(title: &str, reports: &[ChapterReport]) -> String {
+    let mut content = format!("# {title}\n\n| Chapter | Language | Count |\n|---|---|---|\n");
+    for report in reports {
+        for (language, count) in &report.languages {
+            let chapter = report.chapter.replace('|', "\\|");
+            content += &format!("| {} | {} | {} |\n", chapter, language, count);
+        }
+    }
+    content
+}
+
+/// Returns a copy of `book` with the inline codes of its chapters highlighted,
+/// leaving `book` itself as it is.
+pub fn process_book(book: &Book, cfg: &Configuration) -> Result<Book> {
+    run_with(cfg, None, book.clone())
+}
+
+/// Highlights the inline codes of a markdown document.
+pub fn highlight_inline(markdown: &str, cfg: &Configuration) -> String {
+    let mut chapter = Chapter {
+        content: markdown.to_string(),
+        ..Default::default()
+    };
+    highlight_chapter(&mut chapter, cfg);
+    chapter.content
+}
+
+/// Highlights the inline codes of a markdown document like [`highlight_inline`],
+/// also returning the issues found, which are logged as well.
+///
+/// ```
+/// use mdbook_inline_highlighting::{Configuration, highlight_inline_with_diagnostics};
+///
+/// let (output, diagnostics) =
+///     highlight_inline_with_diagnostics("`[rust x`", &Configuration::default());
+/// assert_eq!("`[rust x`", output);
+/// assert_eq!(log::Level::Error, diagnostics[0].level);
+/// assert!(diagnostics[0].message.contains("missing closing character"));
+/// ```
+pub fn highlight_inline_with_diagnostics(
+    markdown: &str,
+    cfg: &Configuration,
+) -> (String, Vec<Diagnostic>) {
+    logging::collect(|| highlight_inline(markdown, cfg))
+}
+
+/// Highlights the chapters of `items` and their sub-chapters, in parallel with the
+/// `rayon` feature, returning the reports and logging the messages in the order of
+/// `Book::for_each_mut` either way.
+fn highlight_items(
+    items: &mut [BookItem],
+    config: &Configuration,
+    cache: Option<&Cache>,
+) -> Vec<ChapterReport> {
+    let highlight_item = |item: &mut BookItem| match item {
+        BookItem::Chapter(chapter) => {
+            let mut reports = vec![];
+            if !(config.skip_drafts && chapter.is_draft_chapter()) {
+                reports.push(match cache {
+                    Some(cache) => cache.highlight(chapter, config),
+                    None => highlight_chapter(chapter, config),
+                });
+            }
+            reports.extend(highlight_items(&mut chapter.sub_items, config, cache));
+            reports
+        }
+        _ => vec![],
+    };
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        let highlighted: Vec<_> = items
+            .par_iter_mut()
+            .map(|item| logging::defer(|| highlight_item(item)))
+            .collect();
+        let mut reports = vec![];
+        for (item_reports, diagnostics) in highlighted {
+            logging::replay(config, diagnostics);
+            reports.extend(item_reports);
+        }
+        reports
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        items.iter_mut().flat_map(highlight_item).collect()
+    }
+}
+
+/// What was found while highlighting a chapter.
+#[derive(Debug, Default)]
+pub(crate) struct ChapterReport {
+    /// Name of the chapter.
+    pub chapter: String,
+    /// How many inline codes were highlighted with each language.
+    pub languages: BTreeMap<String, usize>,
+}
+
+impl ChapterReport {
+    /// Returns the number of highlighted inline codes.
+    pub fn highlighted(&self) -> usize {
+        self.languages.values().sum()
+    }
+}
+
+pub(crate) fn highlight_chapter(chapter: &mut Chapter, config: &Configuration) -> ChapterReport {
+    let mut report = ChapterReport {
+        chapter: chapter.name.clone(),
+        ..Default::default()
+    };
+
+    let parser = new_cmark_parser(
+        &chapter.content,
+        config.smart_punctuation,
+        config.math,
+        config.front_matter,
+    );
+    let mut parsed: Vec<_> = parser.into_offset_iter().collect();
+    if config.merge_adjacent {
+        parsed = merge_adjacent(parsed, config);
+    }
+    let mut events = vec![];
+    let mut style = SourceStyle::preserving(config);
+    let mut overrides = ChapterOverrides::default();
+    let mut in_front_matter = false;
+    let mut depth = 0;
+    let mut scoped_default: Option<ScopedDefault> = None;
+    let mut has_directive_default = false;
+    let mut paragraph: Option<Range<usize>> = None;
+    // The headings of the current section and the sections containing it.
+    let mut sections: Vec<(HeadingLevel, String)> = vec![];
+    let mut heading: Option<(HeadingLevel, String)> = None;
+    // Whether an inline code was replaced, without which the chapter is kept as written
+    // rather than reformatted by serializing it.
+    let mut changed = false;
+    for (event, range) in parsed {
+        style.record(&event, &chapter.content[range.clone()]);
+        match &event {
+            Event::Start(_) => {
+                if let Some(scoped) = &mut scoped_default
+                    && scoped.depth == depth
+                {
+                    scoped.active = true;
+                }
+                depth += 1;
+            }
+            Event::End(_) => {
+                depth -= 1;
+                if scoped_default
+                    .as_ref()
+                    .is_some_and(|scoped| scoped.active && scoped.depth == depth)
+                {
+                    scoped_default = None;
+                }
+            }
+            Event::Html(html) => {
+                if let Some(language) = parse_lang_directive(html) {
+                    has_directive_default = true;
+                    scoped_default = Some(ScopedDefault {
+                        language: language.to_string(),
+                        // The directive itself is nested in an HTML block.
+                        depth: depth - 1,
+                        active: false,
                     });
                 }
-                match cmark(events.iter(), &mut buf).map(|_| buf) {
-                    Ok(result) => chapter.content = result,
-                    Err(error) => {
-                        log::error!("Markdown serialization failed: {}", error);
+            }
+            _ => {}
+        }
+        match &event {
+            Event::Start(Tag::MetadataBlock(MetadataBlockKind::PlusesStyle)) => {
+                in_front_matter = true
+            }
+            Event::End(TagEnd::MetadataBlock(_)) => in_front_matter = false,
+            Event::Start(Tag::Paragraph) => paragraph = Some(range.clone()),
+            Event::End(TagEnd::Paragraph) => paragraph = None,
+            Event::Start(Tag::Heading { level, .. }) => heading = Some((*level, String::new())),
+            Event::Text(text) | Event::Code(text) if heading.is_some() => {
+                if let Some((_, title)) = &mut heading {
+                    title.push_str(text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, title)) = heading.take() {
+                    sections.retain(|(outer, _)| *outer < level);
+                    sections.push((level, title.trim().to_string()));
+                }
+            }
+            Event::Text(text) if in_front_matter => {
+                overrides = ChapterOverrides::from_front_matter(text).unwrap_or_else(|error| {
+                    logging::log(
+                        config,
+                        Level::Error,
+                        format_args!("invalid front matter in chapter `{}`: {}", chapter, error),
+                    );
+                    ChapterOverrides::default()
+                })
+            }
+            _ => {}
+        }
+        // The front matter configures the preprocessor rather than being content.
+        if in_front_matter || matches!(event, Event::End(TagEnd::MetadataBlock(_))) {
+            changed = true;
+            continue;
+        }
+        let in_section = config.sections.is_empty()
+            || sections
+                .iter()
+                .any(|(_, title)| config.sections.contains(title));
+        let default_language = scoped_default
+            .as_ref()
+            .filter(|scoped| scoped.active)
+            .map(|scoped| scoped.language.as_str())
+            .or(overrides.default_language.as_deref())
+            .or(config.default_language.as_deref());
+        let misfenced = matches!(event, Event::Code(_))
+            && config
+                .detect_misfenced
+                .is_some_and(|length| range.len() >= length)
+            && paragraph.as_ref().is_some_and(|paragraph| {
+                chapter.content[paragraph.clone()].trim() == chapter.content[range.clone()].trim()
+            });
+        if misfenced {
+            logging::log(
+                config,
+                Level::Warn,
+                format_args!(
+                    "long inline code making up a whole paragraph in chapter `{}` may be meant as fenced code block",
+                    chapter
+                ),
+            );
+        }
+        events.push(match event {
+            Event::Code(code) if misfenced && config.misfenced_plain => Event::Code(code),
+            Event::Code(code) if !overrides.disable && in_section => {
+                let mut attributes = vec![];
+                if config.source_position_attrs {
+                    let line = chapter.content[..range.start].matches('\n').count() + 1;
+                    attributes.push(("data-source-line", line.to_string()));
+                }
+                let inline =
+                    parse_inline_code(code.as_ref(), default_language, attributes, config, chapter);
+                let replacement = match inline {
+                    // Markdown has no empty inline code.
+                    Inline::Code(c) if c.is_empty() => Event::Html("<code></code>".into()),
+                    Inline::Code(c) if config.theme_aware_plain => {
+                        let theme = config.html_theme.as_deref().unwrap_or(DEFAULT_HTML_THEME);
+                        let class = format!("{PLAIN_THEME_CLASS_PREFIX}{}", slugify(theme));
+                        emit(wrap("code", &[("class", class)], &escape_html(&c)), config)
+                    }
+                    // Written as HTML, since the inline code would be read as specifying a
+                    // language when processing the chapter again.
+                    Inline::Code(c) if c != *code && reads_as_spec(&c, config) => {
+                        emit(wrap("code", &[], &escape_html(&c)), config)
+                    }
+                    Inline::Code(c) => Event::Code(c.into()),
+                    Inline::Html { html, language } => {
+                        *report.languages.entry(language).or_default() += 1;
+
+                        if config.validate_html && !is_well_formed(&html) {
+                            logging::log(
+                                config,
+                                Level::Error,
+                                format_args!(
+                                    "malformed HTML `{}` generated for `{}` in chapter `{}`",
+                                    html, code, chapter
+                                ),
+                            );
+                        }
+                        emit(html, config)
                     }
+                    Inline::Removed => Event::Html("".into()),
                 };
-            };
+                changed |= !matches!(&replacement, Event::Code(c) if *c == code);
+                replacement
+            }
+            event => event,
         });
-        Ok(book)
     }
+    let capacity = config
+        .serialize_buffer_capacity
+        .map_or(0, |factor| (chapter.content.len() as f64 * factor) as usize);
+    let serialized = if changed || config.normalize_output {
+        serialize(&events, &style, capacity)
+    } else {
+        Ok(chapter.content.clone())
+    };
+    match serialized {
+        Ok(mut result) => {
+            if config.normalize_output {
+                result = normalize_line_endings(result.trim_start_matches(BOM));
+            }
+            if let Some(threshold) = config.warn_on_large_output
+                && result.len() > threshold
+            {
+                logging::log(
+                    config,
+                    Level::Warn,
+                    format_args!(
+                        "chapter `{}` grew from {} to {} bytes by highlighting, over the threshold of {} bytes",
+                        chapter,
+                        chapter.content.len(),
+                        result.len(),
+                        threshold
+                    ),
+                );
+            }
+            chapter.content = result
+        }
+        Err(error) => {
+            logging::log(
+                config,
+                Level::Error,
+                format_args!("Markdown serialization failed: {}", error),
+            );
+        }
+    };
+    let has_default_language = has_directive_default
+        || overrides.default_language.is_some()
+        || config.default_language.is_some();
+    if config.warn_unused_default
+        && has_default_language
+        && !overrides.disable
+        && report.highlighted() == 0
+    {
+        logging::log(
+            config,
+            Level::Info,
+            format_args!(
+                "chapter `{}` has a default language but no highlighted inline code",
+                chapter
+            ),
+        );
+    }
+    report
+}
 
-    fn supports_renderer(&self, renderer: &str) -> Result<bool> {
-        Ok(renderer == "html")
+/// Merges inline codes with the same language specification which are separated by
+/// nothing but whitespace, e.g. `` `[rust] a` `[rust] b` `` into `` `[rust] a b` ``.
+fn merge_adjacent<'a>(
+    events: Vec<(Event<'a>, Range<usize>)>,
+    config: &Configuration,
+) -> Vec<(Event<'a>, Range<usize>)> {
+    let mut merged: Vec<(Event, Range<usize>)> = Vec::with_capacity(events.len());
+    for (event, range) in events {
+        if let Event::Code(code) = &event
+            && let Some((lang, rest)) = split_spec(code)
+        {
+            let previous = match merged.as_slice() {
+                [.., (Event::Code(previous), _), (Event::Text(space), _)]
+                    if space.trim().is_empty() =>
+                {
+                    Some((previous, space.as_ref()))
+                }
+                [.., (Event::Code(previous), _)] => Some((previous, "")),
+                _ => None,
+            };
+            let lang = config.resolve_language(lang);
+            if let Some((previous, space)) = previous
+                && split_spec(previous)
+                    .is_some_and(|(previous, _)| config.resolve_language(previous) == lang)
+            {
+                let rest = config.strip_separator(lang, rest).unwrap_or(rest);
+                let code = format!("{previous}{space}{rest}");
+                if !space.is_empty() {
+                    merged.pop();
+                }
+                if let Some((previous, previous_range)) = merged.last_mut() {
+                    *previous = Event::Code(code.into());
+                    previous_range.end = range.end;
+                }
+                continue;
+            }
+        }
+        merged.push((event, range));
+    }
+    merged
+}
+
+/// Encodes the ASCII punctuation of `html` outside of its tags and entities as
+/// numeric entities, since markdown reads the text between inline HTML tags as
+/// markdown, e.g. `*` as emphasis, `\\` as an escape and `` ` `` as inline code.
+fn escape_markdown(html: &str) -> String {
+    let mut escaped = String::with_capacity(html.len());
+    let mut in_tag = false;
+    let mut in_entity = false;
+    for ch in html.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            '&' if !in_tag => in_entity = true,
+            ';' if in_entity => in_entity = false,
+            ch if !in_tag && !in_entity && ch.is_ascii_punctuation() => {
+                escaped.push_str(&format!("&#{};", ch as u32));
+                continue;
+            }
+            _ => {}
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// Converts the CRLF and CR line endings of `text` to LF.
+fn normalize_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Returns the event putting the generated `html` into the chapter.
+fn emit(html: String, config: &Configuration) -> Event<'static> {
+    match config.emission {
+        Emission::RawHtml if config.target == Target::Html => {
+            Event::Html(escape_markdown(&html).into())
+        }
+        Emission::RawHtml => Event::Html(html.into()),
+        Emission::DataUri => {
+            Event::Code(format!("{DATA_URI_PREFIX}{}", percent_encode(&html)).into())
+        }
+        Emission::EntityEncode => {
+            // `&` first, so the entities of the markup are encoded themselves.
+            let html = html.replace('&', "&amp;");
+            let html = html.replace('<', "&lt;").replace('>', "&gt;");
+            Event::Html(escape_markdown(&html).into())
+        }
+    }
+}
+
+/// Percent-encodes every byte of `text` but unreserved URI characters.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// How the fenced code blocks, emphasis and headings of a chapter are written, in
+/// order, recorded for those to be preserved when serializing it.
+#[derive(Default)]
+struct SourceStyle<'a> {
+    preserve_fences: bool,
+    preserve_emphasis: bool,
+    preserve_headings: bool,
+    fences: Vec<char>,
+    emphasis: Vec<char>,
+    /// The underline of each heading, `None` if prefixed with `#`.
+    setext_headings: Vec<Option<&'a str>>,
+}
+
+impl<'a> SourceStyle<'a> {
+    /// Records the styles that `config` preserves.
+    fn preserving(config: &Configuration) -> Self {
+        SourceStyle {
+            preserve_fences: config.preserve_fence_char,
+            preserve_emphasis: config.preserve_emphasis_char,
+            preserve_headings: config.preserve_heading_style,
+            ..Default::default()
+        }
+    }
+
+    fn preserving_all() -> Self {
+        SourceStyle {
+            preserve_fences: true,
+            preserve_emphasis: true,
+            preserve_headings: true,
+            ..Default::default()
+        }
+    }
+
+    /// Records the style of `event`, written as `source`.
+    fn record(&mut self, event: &Event, source: &'a str) {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(_))) if self.preserve_fences => {
+                let fence = source.trim_start().chars().next();
+                self.fences
+                    .push(fence.filter(|&ch| ch == '~').unwrap_or('`'));
+            }
+            Event::Start(Tag::Emphasis | Tag::Strong) if self.preserve_emphasis => {
+                let token = source.chars().next();
+                self.emphasis
+                    .push(token.filter(|&ch| ch == '_').unwrap_or('*'));
+            }
+            Event::Start(Tag::Heading { .. }) if self.preserve_headings => {
+                let heading = source.trim_end();
+                let underline = heading.lines().last().map(str::trim);
+                self.setext_headings
+                    .push(underline.filter(|_| !heading.starts_with('#')));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Serializes `events`, writing the fenced code blocks, emphasis and headings as
+/// recorded in `style` in order, and with the defaults once those are exhausted.
+/// The result starts with `capacity`.
+fn serialize(
+    events: &[Event],
+    style: &SourceStyle,
+    capacity: usize,
+) -> std::result::Result<String, CmarkError> {
+    let mut buf = String::with_capacity(capacity);
+    let mut fences = style.fences.iter();
+    let mut emphasis = style.emphasis.iter();
+    let mut open_emphasis = vec![];
+    let mut setext_headings = style.setext_headings.iter();
+    let mut setext_heading = None;
+    let mut options = CmarkOptions::default();
+    let mut state = None;
+    for event in events {
+        if let Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(_))) = event
+            && let Some(&fence) = fences.next()
+        {
+            options.code_block_token = fence;
+        }
+        let emphasis_token = match event {
+            Event::Start(Tag::Emphasis | Tag::Strong) => {
+                let token = emphasis.next().copied();
+                open_emphasis.push(token);
+                token
+            }
+            Event::End(TagEnd::Emphasis | TagEnd::Strong) => open_emphasis.pop().flatten(),
+            _ => None,
+        };
+        if let Some(token) = emphasis_token {
+            options.emphasis_token = token;
+            options.strong_token = if token == '_' { "__" } else { "**" };
+        }
+        state = Some(cmark_resume_with_options(
+            std::iter::once(event),
+            &mut buf,
+            state,
+            options.clone(),
+        )?);
+        match event {
+            Event::End(TagEnd::CodeBlock) => {
+                options.code_block_token = CmarkOptions::default().code_block_token;
+            }
+            Event::Start(Tag::Heading { level, .. }) => {
+                setext_heading = setext_headings.next().copied().flatten();
+                let prefix = format!("{} ", "#".repeat(*level as usize));
+                if setext_heading.is_some() && buf.ends_with(&prefix) {
+                    buf.truncate(buf.len() - prefix.len());
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(underline) = setext_heading.take() {
+                    buf.push('\n');
+                    buf.push_str(underline);
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some(state) = state {
+        state.finalize(&mut buf)?;
     }
+    Ok(buf)
+}
+
+/// The default language set by a `<!-- highlight-lang: LANGUAGE -->` directive for
+/// the block following it.
+struct ScopedDefault {
+    language: String,
+    depth: usize,
+    /// Whether the block following the directive has started.
+    active: bool,
 }
 
-fn new_cmark_parser<'a>(text: &'a str, smart_punctuation: bool) -> Parser<'a> {
+fn parse_lang_directive(html: &str) -> Option<&str> {
+    let comment = html.trim().strip_prefix("<!--")?.strip_suffix("-->")?;
+    let language = comment.trim().strip_prefix(LANG_DIRECTIVE)?.trim();
+    (!language.is_empty()).then_some(language)
+}
+
+fn new_cmark_parser<'a>(
+    text: &'a str,
+    smart_punctuation: bool,
+    math: bool,
+    front_matter: bool,
+) -> Parser<'a> {
     let mut options = Options::empty();
     options.insert(Options::ENABLE_TABLES);
     options.insert(Options::ENABLE_FOOTNOTES);
     options.insert(Options::ENABLE_STRIKETHROUGH);
     options.insert(Options::ENABLE_TASKLISTS);
     options.insert(Options::ENABLE_HEADING_ATTRIBUTES);
+    if front_matter {
+        options.insert(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS);
+    }
     if smart_punctuation {
         options.insert(Options::ENABLE_SMART_PUNCTUATION);
     }
+    if math {
+        options.insert(Options::ENABLE_MATH);
+    }
     Parser::new_ext(text, options)
 }
 
-/// Returns a tuple with the first item being the new content and the second item
-/// a boolean whether it is an HTML node.
+/// Rewrites the language specifications of all inline codes in `content` to their
+/// canonical form, e.g. `[LANGUAGE] TEXT`, resolving aliases and inserting a missing
+/// separator after the specification. The content is not converted to HTML, and
+/// returned as it is if no specification changed.
+pub fn canonicalize(content: &str, cfg: &Configuration) -> String {
+    let mut in_front_matter = false;
+    let mut style = SourceStyle::preserving_all();
+    let mut changed = false;
+    let mut events = vec![];
+    for (event, range) in
+        new_cmark_parser(content, false, cfg.math, cfg.front_matter).into_offset_iter()
+    {
+        style.record(&event, &content[range]);
+        events.push(match event {
+            Event::Code(code) => match canonical_spec(&code, cfg) {
+                Some(canonical) => {
+                    changed |= canonical != *code;
+                    Event::Code(canonical.into())
+                }
+                None => Event::Code(code),
+            },
+            Event::Start(Tag::MetadataBlock(_)) => {
+                in_front_matter = true;
+                event
+            }
+            Event::End(TagEnd::MetadataBlock(_)) => {
+                in_front_matter = false;
+                event
+            }
+            Event::Text(text) if in_front_matter => Event::Html(text),
+            event => event,
+        });
+    }
+    if !changed {
+        return content.to_string();
+    }
+
+    match serialize(&events, &style, content.len()) {
+        Ok(buf) => buf,
+        Err(error) => {
+            logging::log(
+                cfg,
+                Level::Error,
+                format_args!("Markdown serialization failed: {}", error),
+            );
+            content.to_string()
+        }
+    }
+}
+
+/// Returns `code` with its language specification in canonical form, keeping the
+/// syntax it is written in, or `None` if `cfg` reads no specification in it.
+fn canonical_spec(code: &str, cfg: &Configuration) -> Option<String> {
+    // The canonical language and the rest starting with its separator.
+    let canonical = |lang: &str, rest: &str| {
+        let lang = if cfg.normalize_language {
+            lang.to_lowercase()
+        } else {
+            lang.to_string()
+        };
+        if lang.chars().count() < cfg.min_language_length {
+            return None;
+        }
+        let lang = cfg.resolve_language(&lang);
+        let separator = cfg.separator_for(lang);
+        let rest = cfg.strip_separator(lang, rest).unwrap_or(rest);
+        Some((lang.to_string(), format!("{separator}{rest}")))
+    };
+    if let Some((lang, rest)) = split_comment_spec(code, cfg) {
+        let (lang, rest) = canonical(lang, rest)?;
+        return Some(format!(
+            "{COMMENT_SPEC_START}{lang}{COMMENT_SPEC_END}{rest}"
+        ));
+    }
+    if let Some((lang, rest)) = split_sigil_spec(code, cfg) {
+        let (lang, rest) = canonical(lang, rest)?;
+        let sigil = cfg.sigil.unwrap_or(DEFAULT_SIGIL);
+        return Some(format!("{sigil}{lang}{rest}"));
+    }
+    if cfg.syntax == Syntax::Brackets && code.starts_with(LANG_SPEC_START) {
+        if cfg.doubled_delimiter_escape
+            && code[LANG_SPEC_START.len_utf8()..].starts_with(LANG_SPEC_START)
+        {
+            return None;
+        }
+        let (lang, rest) = split_spec(code)?;
+        // A ` #ID` suffix is kept as written.
+        let (lang, id) = match lang.split_once(DESCRIBED_BY_PREFIX) {
+            Some((name, id)) if !id.is_empty() && !id.contains(char::is_whitespace) => {
+                (name.trim_end(), &lang[name.len()..])
+            }
+            _ => (lang, ""),
+        };
+        let (lang, rest) = canonical(lang, rest)?;
+        return Some(format!("{LANG_SPEC_START}{lang}{id}{LANG_SPEC_END}{rest}"));
+    }
+    if !code.starts_with(ESCAPE_CHAR)
+        && cfg.bare_language
+        && let Some((written, lang, _)) = split_bare_language(code, cfg)
+    {
+        return Some(format!("{lang}{}", &code[written.len()..]));
+    }
+    None
+}
+
+/// Splits `[LANGUAGE]REST` into the language and the rest following the closing
+/// character. Returns `None` if `code` does not start with a language specification,
+/// including what looks like a markdown link.
+fn split_spec(code: &str) -> Option<(&str, &str)> {
+    code.strip_prefix(LANG_SPEC_START)?
+        .split_once(LANG_SPEC_END)
+        .filter(|(lang, rest)| !lang.is_empty() && !rest.starts_with('('))
+}
+
+/// Splits `<!--LANGUAGE-->REST` into the trimmed language and the rest if `config`
+/// enables comment specifications.
+fn split_comment_spec<'a>(code: &'a str, config: &Configuration) -> Option<(&'a str, &'a str)> {
+    if !config.comment_spec {
+        return None;
+    }
+    let (lang, rest) = code
+        .strip_prefix(COMMENT_SPEC_START)?
+        .split_once(COMMENT_SPEC_END)?;
+    Some((lang.trim(), rest)).filter(|(lang, _)| !lang.is_empty())
+}
+
+/// Splits `#LANGUAGE REST` into the language and the rest if `config` uses the
+/// sigil syntax.
+fn split_sigil_spec<'a>(code: &'a str, config: &Configuration) -> Option<(&'a str, &'a str)> {
+    if config.syntax != Syntax::Sigil {
+        return None;
+    }
+    let rest = code.strip_prefix(config.sigil.unwrap_or(DEFAULT_SIGIL))?;
+    let end = rest.find(|ch| !is_language_char(ch)).unwrap_or(rest.len());
+    (end > 0).then(|| rest.split_at(end))
+}
+
+/// Splits a leading known language followed by whitespace off `code`, returning the
+/// language as written, its canonical name and the rest.
+fn split_bare_language<'a>(
+    code: &'a str,
+    config: &'a Configuration,
+) -> Option<(&'a str, &'a str, &'a str)> {
+    let (written, rest) = code.split_once(char::is_whitespace)?;
+    let lang = config.resolve_language(written);
+    config
+        .is_known_language(lang)
+        .then_some((written, lang, rest))
+}
+
+/// What an inline code is turned into.
+#[derive(Debug, PartialEq)]
+enum Inline {
+    /// Plain inline code.
+    Code(String),
+    /// HTML of the code highlighted as `language`.
+    Html { html: String, language: String },
+    /// Nothing, the inline code is removed.
+    Removed,
+}
+
+/// Parses `code`, adding `attributes` to the element of the code if highlighted.
 fn parse_inline_code(
     code: &str,
     default_language: Option<&str>,
+    mut attributes: Vec<(&'static str, String)>,
+    config: &Configuration,
     chapter: &Chapter,
-) -> (String, bool) {
+) -> Inline {
+    // `[none]` follows the default language like unmarked code does.
+    let default_language = default_language.filter(|_| config.highlight_unmarked != Some(false));
+    if let Some((lang, rest)) = split_comment_spec(code, config) {
+        return highlight_spec(
+            lang,
+            rest,
+            default_language,
+            code,
+            attributes,
+            config,
+            chapter,
+        );
+    }
+    if let Some((lang, rest)) = split_sigil_spec(code, config) {
+        return highlight_spec(
+            lang,
+            rest,
+            default_language,
+            code,
+            attributes,
+            config,
+            chapter,
+        );
+    }
     let mut chars = code.chars();
     match chars.next() {
-        Some(LANG_SPEC_START) => {}
+        Some(LANG_SPEC_START) if config.syntax == Syntax::Brackets => {
+            if config.doubled_delimiter_escape && chars.as_str().starts_with(LANG_SPEC_START) {
+                return highlight_or_plain(
+                    chars.as_str(),
+                    default_language,
+                    code,
+                    &attributes,
+                    config,
+                );
+            }
+        }
         Some(ch) => {
-            let result: &str = if ch == ESCAPE_CHAR {
-                chars.as_str()
+            if ch != ESCAPE_CHAR
+                && config.bare_language
+                && let Some((written, lang, rest)) = split_bare_language(code, config)
+            {
+                record_original_language(&mut attributes, written, config);
+                return Inline::Html {
+                    html: inline_with_highlighting(rest, lang, code, &attributes, config),
+                    language: lang.to_string(),
+                };
+            }
+            let result = if ch == ESCAPE_CHAR && chars.as_str().is_empty() {
+                // A lone escape character has nothing to escape and is code itself.
+                Cow::Borrowed(code)
+            } else if ch == ESCAPE_CHAR && config.collapse_escapes {
+                // Each escape character escapes the next one, so only every second
+                // one of a run is kept.
+                let escaped = code.trim_start_matches(ESCAPE_CHAR);
+                let run = code.len() - escaped.len();
+                Cow::Owned(ESCAPE_CHAR.to_string().repeat(run / 2) + escaped)
+            } else if ch == ESCAPE_CHAR {
+                Cow::Borrowed(chars.as_str())
             } else {
-                code
+                Cow::Borrowed(code)
             };
+            let default_language = default_language.filter(|_| {
+                config
+                    .code_pattern
+                    .as_ref()
+                    .is_none_or(|pattern| pattern.is_match(&result))
+            });
 
-            return if let Some(l) = default_language {
-                (inline_with_highlighting(result, l), true)
-            } else {
-                (result.to_string(), false)
-            };
+            return highlight_or_plain(&result, default_language, code, &attributes, config);
         }
-        None => return (String::new(), false),
+        None => return Inline::Code(String::new()),
     };
     let mut lang = String::new();
     loop {
@@ -109,87 +1044,565 @@ fn parse_inline_code(
             Some(LANG_SPEC_END) => break,
             Some(ch) => lang.push(ch),
             None => {
-                log::error!(
-                    "missing closing character `{}` in chapter `{}`",
-                    LANG_SPEC_END,
-                    chapter
-                );
-                return if let Some(l) = default_language {
-                    (inline_with_highlighting(code, l), true)
-                } else {
-                    (code.into(), false)
-                };
+                // With a default language this is simply code starting with the
+                // opening character, e.g. a slice pattern `[first, ..`.
+                if default_language.is_none() {
+                    logging::log(
+                        config,
+                        Level::Error,
+                        format_args!(
+                            "missing closing character `{}` in chapter `{}`",
+                            LANG_SPEC_END, chapter
+                        ),
+                    );
+                }
+                return highlight_or_plain(code, default_language, code, &attributes, config);
             }
         };
     }
-    let language: Option<&str> = if lang == "none" {
+    if lang.is_empty() {
+        // `[]` is common in code itself, e.g. an empty array.
+        return highlight_or_plain(code, default_language, code, &attributes, config);
+    }
+    if chars.as_str().starts_with('(') {
+        logging::log(
+            config,
+            Level::Warn,
+            format_args!(
+                "`{}` in chapter `{}` looks like a markdown link rather than a language specification",
+                code, chapter
+            ),
+        );
+        return highlight_or_plain(code, default_language, code, &attributes, config);
+    }
+    // `[LANGUAGE #ID]` refers to an element describing the code, e.g. a legend.
+    let (lang, described_by) = match lang.split_once(DESCRIBED_BY_PREFIX) {
+        Some((lang, id)) if !id.is_empty() && !id.contains(char::is_whitespace) => {
+            (lang.trim_end(), Some(id))
+        }
+        _ => (lang.as_str(), None),
+    };
+    if let Some(id) = described_by {
+        attributes.push(("aria-describedby", id.to_string()));
+    }
+    highlight_spec(
+        lang,
+        chars.as_str(),
+        default_language,
+        code,
+        attributes,
+        config,
+        chapter,
+    )
+}
+
+/// Returns whether `code` starts like a language specification or an escaped one.
+fn reads_as_spec(code: &str, config: &Configuration) -> bool {
+    let starts_spec = match config.syntax {
+        Syntax::Brackets => code.starts_with(LANG_SPEC_START),
+        Syntax::Sigil => code.starts_with(config.sigil.unwrap_or(DEFAULT_SIGIL)),
+    };
+    starts_spec
+        || (config.comment_spec && code.starts_with(COMMENT_SPEC_START))
+        || (code.starts_with(ESCAPE_CHAR) && code.len() > ESCAPE_CHAR.len_utf8())
+        || (config.bare_language && split_bare_language(code, config).is_some())
+}
+
+/// Highlights `rest`, what follows the language specification of `code`, as `lang`.
+fn highlight_spec(
+    lang: &str,
+    rest: &str,
+    default_language: Option<&str>,
+    code: &str,
+    mut attributes: Vec<(&'static str, String)>,
+    config: &Configuration,
+    chapter: &Chapter,
+) -> Inline {
+    if lang.chars().count() < config.min_language_length {
+        logging::log(
+            config,
+            Level::Debug,
+            format_args!(
+                "`{}` in chapter `{}` is too short for a language",
+                lang, chapter
+            ),
+        );
+        return highlight_or_plain(code, default_language, code, &attributes, config);
+    }
+    let written = lang;
+    // Full Unicode lowercasing, so non-ASCII identifiers fold as well.
+    let normalized = config.normalize_language.then(|| lang.to_lowercase());
+    let lang = normalized.as_deref().unwrap_or(lang);
+    let language: Option<&str> = if lang.eq_ignore_ascii_case("none") {
         default_language
     } else {
-        Some(&lang)
+        Some(config.resolve_language(lang))
     };
-    if chars.next().is_none_or(|ch| ch != ' ') {
-        log::error!(
-            "missing space after language identifier in chapter `{}`",
-            chapter
+    // The separator may depend on the language, so it can only be checked once the
+    // language specification has been read.
+    let separator = config.separator_for(config.resolve_language(lang));
+    let actual_code = config
+        .strip_separator(config.resolve_language(lang), rest)
+        .or_else(|| (config.require_separator == Some(false)).then_some(rest));
+    let Some(actual_code) = actual_code else {
+        logging::log(
+            config,
+            Level::Error,
+            format_args!(
+                "missing separator `{}` after language identifier in chapter `{}`",
+                separator, chapter
+            ),
         );
-        return if let Some(l) = default_language {
-            (inline_with_highlighting(code, l), true)
-        } else {
-            (code.into(), false)
-        };
+        return highlight_or_plain(code, default_language, code, &attributes, config);
     };
-    let actual_code = chars.as_str();
-    match language {
-        Some(l) => (inline_with_highlighting(actual_code, l), true),
-        None => (actual_code.to_string(), false),
+    let prefix = config.body_strip_prefix.as_deref().unwrap_or_default();
+    let suffix = config.body_strip_suffix.as_deref().unwrap_or_default();
+    let actual_code = actual_code
+        .strip_prefix(prefix)
+        .and_then(|code| code.strip_suffix(suffix))
+        .unwrap_or(actual_code);
+    if actual_code.is_empty() && language.is_some() {
+        match config.empty_code_policy {
+            EmptyCodePolicy::EmitEmpty => {}
+            EmptyCodePolicy::Skip => return Inline::Removed,
+            EmptyCodePolicy::Plain => return Inline::Code(String::new()),
+        }
     }
+    if !lang.eq_ignore_ascii_case("none") {
+        record_original_language(&mut attributes, written, config);
+    }
+    highlight_or_plain(actual_code, language, code, &attributes, config)
 }
 
-fn inline_with_highlighting(code: &str, language: &str) -> String {
-    format!("<code class=\"hljs language-{}\">{}</code>", language, code)
+/// Records `lang`, the language as written before resolving aliases, among the
+/// `attributes` of highlighted code if configured.
+fn record_original_language(
+    attributes: &mut Vec<(&'static str, String)>,
+    lang: &str,
+    config: &Configuration,
+) {
+    if config.record_original_language {
+        attributes.push(("data-original-lang", lang.to_string()));
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    #[test]
-    fn html_with_language() {
-        assert_eq!(
-            "<code class=\"hljs language-javascript\">Hello</code>",
-            inline_with_highlighting("Hello", "javascript"),
-        );
+fn highlight_or_plain(
+    code: &str,
+    language: Option<&str>,
+    original: &str,
+    attributes: &[(&str, String)],
+    config: &Configuration,
+) -> Inline {
+    match language {
+        Some(l) => Inline::Html {
+            html: inline_with_highlighting(code, l, original, attributes, config),
+            language: l.to_string(),
+        },
+        None => Inline::Code(code.to_string()),
+    }
+}
+
+/// Returns the HTML of `code` highlighted as `language`, `original` being the
+/// inline code as written in the chapter and `extra_attributes` added to the
+/// element of the code.
+fn inline_with_highlighting(
+    code: &str,
+    language: &str,
+    original: &str,
+    extra_attributes: &[(&str, String)],
+    config: &Configuration,
+) -> String {
+    let code = match &config.strip_trailing_punct {
+        Some(punctuation) => code.trim_end_matches(|ch| punctuation.contains(ch)),
+        None => code,
+    };
+    let code = match &config.tab_replacement {
+        Some(replacement) => Cow::Owned(code.replace('\t', replacement)),
+        None => Cow::Borrowed(code),
+    };
+    if config.target == Target::Jsx {
+        let mut attributes = vec![("lang", language.to_string())];
+        attributes.extend_from_slice(extra_attributes);
+        return wrap(
+            JSX_ELEMENT,
+            &attributes,
+            &format!("{{`{}`}}", escape_template(&code)),
+        );
+    }
+    // Attributes of every generated element, whatever it is.
+    let mut common_attributes = vec![];
+    if config.force_ltr {
+        common_attributes.push(("dir", "ltr".to_string()));
+    }
+    if let Some(nonce) = &config.nonce {
+        common_attributes.push(("nonce", nonce.clone()));
+    }
+    let escaped = escape_html(&code);
+    if config.mark_language() == Some(language) {
+        common_attributes.extend_from_slice(extra_attributes);
+        return wrap("mark", &[], &wrap("code", &common_attributes, &escaped));
+    }
+    if let Some(element) = config.element_for(language) {
+        common_attributes.extend_from_slice(extra_attributes);
+        return wrap(element, &common_attributes, &escaped);
+    }
+    let base_class = match config.base_class_for.get(language) {
+        Some(class) => class.clone(),
+        None => {
+            let class = config.base_class.as_deref().unwrap_or(BASE_CLASS);
+            config.base_class_case.apply(class)
+        }
+    };
+    let mut classes = vec![];
+    if language == AUTO_LANGUAGE {
+        // Without a `language-*` class highlight.js detects the language itself.
+        let class = config.auto_class.clone().unwrap_or(base_class);
+        classes.extend(Some(class).filter(|class| !class.is_empty()));
+    } else if let Some(class) = config.final_class_map.get(language) {
+        classes.push(class.clone());
+    } else {
+        if !base_class.is_empty() && !config.no_base_class_for.iter().any(|l| l == language) {
+            classes.push(base_class);
+        }
+        if config.language_marking.class() {
+            classes.push(format!("language-{}", slugify(language)));
+        }
+        if let Some(group) = config.language_groups.get(language) {
+            classes.push(format!("group-{group}"));
+        }
+        if language == DIFF_LANGUAGE {
+            match code.chars().next() {
+                Some('+') => classes.push(DIFF_ADD_CLASS.to_string()),
+                Some('-') => classes.push(DIFF_DEL_CLASS.to_string()),
+                _ => {}
+            }
+        }
+        if config.dual_theme_classes {
+            if config.theme_classes.is_empty() {
+                classes.extend(DEFAULT_THEME_CLASSES.map(String::from));
+            } else {
+                classes.extend(config.theme_classes.iter().cloned());
+            }
+        }
+    }
+    if let Some(class) = &config.always_class {
+        classes.push(class.clone());
+    }
+    let preserve_whitespace_style = match &config.preserve_whitespace_class {
+        Some(class) if config.preserve_whitespace => {
+            classes.push(class.clone());
+            None
+        }
+        _ => config
+            .preserve_whitespace
+            .then_some(PRESERVE_WHITESPACE_STYLE),
+    };
+    let mut attributes = vec![];
+    if !classes.is_empty() {
+        attributes.push(("class", classes.join(" ")));
+    }
+    if config.language_marking.attribute() && language != AUTO_LANGUAGE {
+        attributes.push(("data-language", language.to_string()));
+    }
+    if config.data_lang && language != AUTO_LANGUAGE {
+        attributes.push(("data-lang", language.to_string()));
+    }
+    if let Some(attribute) = &config.copy_button_attr {
+        attributes.push((attribute, "true".to_string()));
+    }
+    if config.original_as_title {
+        attributes.push(("title", original.to_string()));
+    }
+    let mut styles = vec![];
+    if let Some(style) = preserve_whitespace_style {
+        styles.push(style.to_string());
+    }
+    if let Some(variable) = config.css_var_map.get(language) {
+        styles.push(format!("{LANGUAGE_COLOR_PROPERTY}: var({variable})"));
+    }
+    if !styles.is_empty() {
+        attributes.push(("style", styles.join(" ")));
+    }
+    attributes.extend(common_attributes);
+    attributes.extend_from_slice(extra_attributes);
+    let tag = if language == AUTO_LANGUAGE {
+        config.auto_element.as_ref().or(config.element.as_ref())
+    } else {
+        config.element.as_ref()
+    };
+    let tag = tag.map(String::as_str).unwrap_or(DEFAULT_ELEMENT);
+    let content = Cow::Borrowed(escaped.as_str());
+    #[cfg(feature = "token-wrap")]
+    let content = if config.token_wrap {
+        Cow::Owned(wrap_tokens(&content))
+    } else {
+        content
+    };
+    let content = match config.language_affixes.get(language) {
+        Some(affixes) => {
+            Cow::Owned(escape_html(&affixes.prefix) + &content + &escape_html(&affixes.suffix))
+        }
+        None => content,
+    };
+    let content = match &config.inner_wrapper_class {
+        Some(class) => Cow::Owned(wrap("span", &[("class", class.clone())], &content)),
+        None => content,
+    };
+    let mut element = wrap(tag, &attributes, &content);
+    if config.data_element && language != AUTO_LANGUAGE {
+        element = wrap("data", &[("value", language.to_string())], &element);
+    }
+    if config.noscript_fallback {
+        let fallback = wrap("noscript", &[], &wrap("code", &[], &escaped));
+        element = wrap(
+            "span",
+            &[("class", NOSCRIPT_FALLBACK_CLASS.to_string())],
+            &(element + &fallback),
+        );
+    }
+    if let Some(url) = config.doc_links.get(language) {
+        let href = url.replace(DOC_LINK_CODE_PLACEHOLDER, &percent_encode(&code));
+        element = wrap("a", &[("href", href)], &element);
+    }
+    // Elements following the highlighted code, which are kept in one element with it.
+    let mut siblings = String::new();
+    if config.clipboard_source {
+        siblings += &wrap(
+            "span",
+            &[
+                ("class", CLIPBOARD_SOURCE_CLASS.to_string()),
+                ("hidden", String::new()),
+            ],
+            &escaped,
+        );
+    }
+    if config.language_badge && language != AUTO_LANGUAGE {
+        let class = LANGUAGE_BADGE_CLASS.to_string();
+        siblings += &wrap("sup", &[("class", class)], &escape_html(language));
+    }
+    if !siblings.is_empty() {
+        element = wrap("span", &[], &(element + &siblings));
+    }
+    element
+}
+
+fn wrap(tag: &str, attributes: &[(&str, String)], content: &str) -> String {
+    format!(
+        "<{}{}>{}</{}>",
+        tag,
+        render_attributes(attributes),
+        content,
+        tag
+    )
+}
+
+/// Escapes `code` for a JavaScript template literal.
+fn escape_template(code: &str) -> String {
+    code.replace('\\', "\\\\")
+        .replace('`', "\\`")
+        .replace('$', "\\$")
+}
+
+/// Wraps each whitespace-delimited token of `code` in a `<span class="tok">`.
+#[cfg(feature = "token-wrap")]
+fn wrap_tokens(code: &str) -> String {
+    let mut wrapped = String::new();
+    let mut rest = code;
+    while !rest.is_empty() {
+        let token_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if token_end > 0 {
+            let class = TOKEN_CLASS.to_string();
+            wrapped += &wrap("span", &[("class", class)], &rest[..token_end]);
+        }
+        let space_end = rest[token_end..]
+            .find(|ch: char| !ch.is_whitespace())
+            .map_or(rest.len(), |i| token_end + i);
+        wrapped += &rest[token_end..space_end];
+        rest = &rest[space_end..];
+    }
+    wrapped
+}
+
+/// Returns whether `html` is a single element with balanced tags and quoted
+/// attributes.
+fn is_well_formed(html: &str) -> bool {
+    let mut open = vec![];
+    let mut rest = html;
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            return false;
+        };
+        if open.is_empty() && start > 0 {
+            // Text outside of the element.
+            return false;
+        }
+        let Some(end) = rest[start..].find('>') else {
+            return false;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+        if let Some(name) = tag.strip_prefix('/') {
+            if open.pop() != Some(name) {
+                return false;
+            }
+            if open.is_empty() && !rest.is_empty() {
+                // A second element.
+                return false;
+            }
+        } else {
+            let (name, attributes) = tag.split_once(' ').unwrap_or((tag, ""));
+            if name.is_empty() || !are_attributes_quoted(attributes) {
+                return false;
+            }
+            open.push(name);
+        }
+    }
+    open.is_empty() && !html.is_empty()
+}
+
+/// Returns whether `attributes` consists of `name="value"` pairs only.
+fn are_attributes_quoted(mut attributes: &str) -> bool {
+    loop {
+        attributes = attributes.trim_start();
+        if attributes.is_empty() {
+            return true;
+        }
+        let Some((name, value)) = attributes.split_once("=\"") else {
+            return false;
+        };
+        let Some((_, rest)) = value.split_once('"') else {
+            return false;
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return false;
+        }
+        attributes = rest;
+    }
+}
+
+fn render_attributes(attributes: &[(&str, String)]) -> String {
+    attributes
+        .iter()
+        .map(|(name, value)| format!(" {}=\"{}\"", name, escape_html(value)))
+        .collect()
+}
+
+/// Returns whether `ch` may be part of a language name following a sigil, which are
+/// the characters kept in its class name.
+fn is_language_char(ch: char) -> bool {
+    ch.is_alphanumeric() || "-_+#.".contains(ch)
+}
+
+/// Replaces everything that cannot be part of a single class name.
+fn slugify(language: &str) -> String {
+    language
+        .chars()
+        .map(|ch| if is_language_char(ch) { ch } else { '-' })
+        .collect()
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            // Splits the cell when in a table row, even in an attribute value.
+            '|' => escaped.push_str("&#124;"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::config::{Affixes, Case, LanguageMarking, SeparatorClass};
+    use regex::Regex;
+
+    #[test]
+    fn html_with_language() {
+        assert_eq!(
+            "<code class=\"hljs language-javascript\">Hello</code>",
+            inline_with_highlighting(
+                "Hello",
+                "javascript",
+                "Hello",
+                &[],
+                &Configuration::default()
+            ),
+        );
+    }
+
+    #[test]
+    fn html_without_base_class() {
+        let config = Configuration {
+            no_base_class_for: vec!["mermaid".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"language-mermaid\">Hello</code>",
+            inline_with_highlighting("Hello", "mermaid", "Hello", &[], &config),
+        );
+        assert_eq!(
+            "<code class=\"hljs language-rust\">Hello</code>",
+            inline_with_highlighting("Hello", "rust", "Hello", &[], &config),
+        );
     }
 
     #[test]
     fn invalid_inline() {
         assert_eq!(
-            ("[forgot-to-close oops".to_string(), false),
-            parse_inline_code("[forgot-to-close oops", None, &Chapter::default())
+            Inline::Code("[forgot-to-close oops".to_string()),
+            parse_inline_code(
+                "[forgot-to-close oops",
+                None,
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            )
         );
         assert_eq!(
-            (
-                "<code class=\"hljs language-javascript\">[forgot-to-close oops</code>".to_string(),
-                true
-            ),
+            Inline::Html {
+                html: "<code class=\"hljs language-javascript\">[forgot-to-close oops</code>"
+                    .to_string(),
+                language: "javascript".to_string()
+            },
             parse_inline_code(
                 "[forgot-to-close oops",
                 Some("javascript"),
+                vec![],
+                &Configuration::default(),
                 &Chapter::default()
             )
         );
         assert_eq!(
-            ("[js]var missingSpace;".to_string(), false),
-            parse_inline_code("[js]var missingSpace;", None, &Chapter::default()),
+            Inline::Code("[js]var missingSpace;".to_string()),
+            parse_inline_code(
+                "[js]var missingSpace;",
+                None,
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            ),
         );
         assert_eq!(
-            (
-                "<code class=\"hljs language-typescript\">[js]var missingSpace;</code>".to_string(),
-                true
-            ),
+            Inline::Html {
+                html: "<code class=\"hljs language-typescript\">[js]var missingSpace;</code>"
+                    .to_string(),
+                language: "typescript".to_string()
+            },
             parse_inline_code(
                 "[js]var missingSpace;",
                 Some("typescript"),
+                vec![],
+                &Configuration::default(),
                 &Chapter::default()
             )
         )
@@ -198,15 +1611,46 @@ mod test {
     #[test]
     fn escaped_inline() {
         assert_eq!(
-            ("[python] x = 1".to_string(), false),
-            parse_inline_code("\\[python] x = 1", None, &Chapter::default())
+            Inline::Code("[python] x = 1".to_string()),
+            parse_inline_code(
+                "\\[python] x = 1",
+                None,
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            )
         );
         assert_eq!(
-            (
-                "<code class=\"hljs language-python\">[Hello</code>".to_string(),
-                true
-            ),
-            parse_inline_code("\\[Hello", Some("python"), &Chapter::default())
+            Inline::Html {
+                html: "<code class=\"hljs language-python\">[Hello</code>".to_string(),
+                language: "python".to_string()
+            },
+            parse_inline_code(
+                "\\[Hello",
+                Some("python"),
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            )
+        );
+    }
+
+    #[test]
+    fn lone_escape_char() {
+        let config = Configuration::default();
+        assert_eq!(r"`\`", highlight_inline(r"`\`", &config));
+        let config = Configuration {
+            collapse_escapes: true,
+            ..config
+        };
+        assert_eq!(r"`\`", highlight_inline(r"`\`", &config));
+        let config = Configuration {
+            default_language: Some("sh".to_string()),
+            ..config
+        };
+        assert_eq!(
+            r#"<code class="hljs language-sh">&#92;</code>"#,
+            highlight_inline(r"`\`", &config)
         );
     }
 
@@ -214,12 +1658,24 @@ mod test {
     fn markdown_without_default_without_language() {
         let expect = String::from("Hello");
         assert_eq!(
-            (expect.clone(), false),
-            parse_inline_code("[none] Hello", None, &Chapter::default()),
+            Inline::Code(expect.clone()),
+            parse_inline_code(
+                "[none] Hello",
+                None,
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            ),
         );
         assert_eq!(
-            (expect.clone(), false),
-            parse_inline_code("Hello", None, &Chapter::default()),
+            Inline::Code(expect.clone()),
+            parse_inline_code(
+                "Hello",
+                None,
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            ),
         )
     }
 
@@ -227,34 +1683,2289 @@ mod test {
     fn markdown_with_default_without_language() {
         let expect = String::from("<code class=\"hljs language-javascript\">Hello</code>");
         assert_eq!(
-            (expect.clone(), true),
-            parse_inline_code("[none] Hello", Some("javascript"), &Chapter::default()),
+            Inline::Html {
+                html: expect.clone(),
+                language: "javascript".to_string()
+            },
+            parse_inline_code(
+                "[none] Hello",
+                Some("javascript"),
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            ),
         );
         assert_eq!(
-            (expect.clone(), true),
-            parse_inline_code("Hello", Some("javascript"), &Chapter::default()),
+            Inline::Html {
+                html: expect.clone(),
+                language: "javascript".to_string()
+            },
+            parse_inline_code(
+                "Hello",
+                Some("javascript"),
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            ),
         );
     }
 
     #[test]
     fn markdown_without_default_with_language() {
         assert_eq!(
-            (
-                "<code class=\"hljs language-javascript\">Hello</code>".to_string(),
-                true
+            Inline::Html {
+                html: "<code class=\"hljs language-javascript\">Hello</code>".to_string(),
+                language: "javascript".to_string()
+            },
+            parse_inline_code(
+                "[javascript] Hello",
+                None,
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
             ),
-            parse_inline_code("[javascript] Hello", None, &Chapter::default()),
         )
     }
 
     #[test]
     fn markdown_with_default_with_language() {
         assert_eq!(
-            (
-                "<code class=\"hljs language-javascript\">Hello</code>".to_string(),
-                true
+            Inline::Html {
+                html: "<code class=\"hljs language-javascript\">Hello</code>".to_string(),
+                language: "javascript".to_string()
+            },
+            parse_inline_code(
+                "[javascript] Hello",
+                Some("python"),
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            ),
+        )
+    }
+
+    #[test]
+    fn base_class_for() {
+        let config = Configuration {
+            base_class_for: [("mermaid".to_string(), "mmd".to_string())].into(),
+            aliases: [("mmd".to_string(), "mermaid".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"mmd language-mermaid\">a</code> <code class=\"hljs language-rust\">b</code>",
+            highlight_inline("`[mmd] a` `[rust] b`", &config)
+        );
+    }
+
+    #[test]
+    fn base_class_case() {
+        let html = |case| {
+            let config = Configuration {
+                base_class_case: case,
+                ..Default::default()
+            };
+            inline_with_highlighting("x", "rust", "x", &[], &config)
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\">x</code>",
+            html(Case::Lower)
+        );
+        assert_eq!(
+            "<code class=\"HLJS language-rust\">x</code>",
+            html(Case::Upper)
+        );
+        assert_eq!(
+            "<code class=\"hljs language-rust\">x</code>",
+            html(Case::AsIs)
+        );
+    }
+
+    #[test]
+    fn auto_detect_class() {
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs\">x = 1</code>".to_string(),
+                language: "auto".to_string()
+            },
+            parse_inline_code(
+                "[auto] x = 1",
+                None,
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            ),
+        );
+        let config = Configuration {
+            auto_class: Some("hljs auto".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs auto\">x = 1</code>".to_string(),
+                language: "auto".to_string()
+            },
+            parse_inline_code("x = 1", Some("auto"), vec![], &config, &Chapter::default()),
+        );
+    }
+
+    fn config_with_js_alias() -> Configuration {
+        Configuration {
+            aliases: [("js".to_string(), "javascript".to_string())].into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn alias_resolved_to_canonical_language() {
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-javascript\">Hello</code>".to_string(),
+                language: "javascript".to_string()
+            },
+            parse_inline_code(
+                "[js] Hello",
+                None,
+                vec![],
+                &config_with_js_alias(),
+                &Chapter::default()
             ),
-            parse_inline_code("[javascript] Hello", Some("python"), &Chapter::default()),
         )
     }
+
+    #[test]
+    fn canonicalize_specs() {
+        let cfg = config_with_js_alias();
+        assert_eq!("`[javascript] foo`", canonicalize("`[js]foo`", &cfg));
+        assert_eq!("`[javascript] foo`", canonicalize("`[js] foo`", &cfg));
+        assert_eq!("`[py] foo`", canonicalize("`[py] foo`", &cfg));
+        assert_eq!("`[]`", canonicalize("`[]`", &cfg));
+        assert_eq!(
+            "+++\n[inline-highlighting]\n+++\n\n`[javascript] foo`",
+            canonicalize(
+                "+++\n[inline-highlighting]\n+++\n\n`[js] foo`",
+                &Configuration {
+                    front_matter: true,
+                    ..config_with_js_alias()
+                }
+            )
+        );
+        assert_eq!("`\\[js]foo`", canonicalize("`\\[js]foo`", &cfg));
+        assert_eq!("`[javascript #l] x`", canonicalize("`[js #l]x`", &cfg));
+        let doubled = Configuration {
+            doubled_delimiter_escape: true,
+            ..config_with_js_alias()
+        };
+        assert_eq!("`[[js]x`", canonicalize("`[[js]x`", &doubled));
+        let sigil = Configuration {
+            syntax: Syntax::Sigil,
+            ..config_with_js_alias()
+        };
+        assert_eq!(
+            "`#javascript x` `[js]x`",
+            canonicalize("`#js x` `[js]x`", &sigil)
+        );
+        let comment = Configuration {
+            comment_spec: true,
+            ..config_with_js_alias()
+        };
+        assert_eq!(
+            "`<!--javascript--> x`",
+            canonicalize("`<!--js-->x`", &comment)
+        );
+        let bare = Configuration {
+            bare_language: true,
+            known_languages: vec!["javascript".to_string()],
+            ..config_with_js_alias()
+        };
+        assert_eq!(
+            "`javascript x` `\\js x` `py x`",
+            canonicalize("`js x` `\\js x` `py x`", &bare)
+        );
+        // Kept as written without a specification to rewrite, and otherwise written
+        // in the same style.
+        let markdown = "Title\n===\n\n* _a_ `[py] b`\n\n~~~\nc\n~~~\n";
+        assert_eq!(markdown, canonicalize(markdown, &cfg));
+        assert_eq!(
+            "Title\n===\n\n* _a_ `[javascript] b`\n\n~~~~\nc\n~~~~",
+            canonicalize(&markdown.replace("py", "js"), &cfg)
+        );
+    }
+
+    #[test]
+    fn task_list_item() {
+        assert_eq!(
+            "* [ ] run <code class=\"hljs language-bash\">make</code>\n* [x] done",
+            highlight_inline(
+                "- [ ] run `[bash] make`\n- [x] done",
+                &Configuration::default()
+            ),
+        );
+    }
+
+    #[test]
+    fn html_container_blocks() {
+        assert_eq!(
+            "<div class=\"admonition\">\n\nUse <code class=\"hljs language-rust\">x</code> here.\n\n</div>\n\nAfter <code class=\"hljs language-js\">y</code>.",
+            highlight_inline(
+                "<div class=\"admonition\">\n\nUse `[rust] x` here.\n\n</div>\n\nAfter `[js] y`.",
+                &Configuration::default()
+            ),
+        );
+    }
+
+    #[test]
+    fn no_logging_silences_errors() {
+        let parse = |config: &Configuration| {
+            logging::capture::logged(|| {
+                parse_inline_code("[oops", None, vec![], config, &Chapter::default());
+            })
+        };
+        assert_eq!(1, parse(&Configuration::default()).len());
+        let config = Configuration {
+            no_logging: true,
+            ..Default::default()
+        };
+        assert!(parse(&config).is_empty());
+    }
+
+    #[test]
+    fn bare_language() {
+        let config = Configuration {
+            bare_language: true,
+            known_languages: vec!["rust".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-rust\">fn main(){}</code>".to_string(),
+                language: "rust".to_string()
+            },
+            parse_inline_code(
+                "rust fn main(){}",
+                None,
+                vec![],
+                &config,
+                &Chapter::default()
+            ),
+        );
+        assert_eq!(
+            Inline::Code("run fn main(){}".to_string()),
+            parse_inline_code(
+                "run fn main(){}",
+                None,
+                vec![],
+                &config,
+                &Chapter::default()
+            ),
+        );
+        assert_eq!(
+            Inline::Code("rust fn main(){}".to_string()),
+            parse_inline_code(
+                "rust fn main(){}",
+                None,
+                vec![],
+                &Configuration {
+                    bare_language: false,
+                    ..config
+                },
+                &Chapter::default()
+            ),
+        );
+    }
+
+    #[test]
+    fn front_matter_overrides() {
+        let config = Configuration {
+            default_language: Some("javascript".to_string()),
+            front_matter: true,
+            ..Default::default()
+        };
+        let front_matter = "+++\n[inline-highlighting]\ndefault-language = \"python\"\n+++";
+        let output = highlight_inline(&format!("{front_matter}\n\n`x`"), &config);
+        assert_eq!("<code class=\"hljs language-python\">x</code>", output);
+        assert_eq!(
+            "<p><code class=\"hljs language-python\">x</code></p>\n",
+            render(&output)
+        );
+        let front_matter = "+++\n[inline-highlighting]\ndisable = true\n+++";
+        assert_eq!(
+            "`[rust] x`",
+            highlight_inline(&format!("{front_matter}\n\n`[rust] x`"), &config),
+        );
+        // Only read if enabled.
+        let config = Configuration {
+            front_matter: false,
+            ..config
+        };
+        assert_eq!(
+            "+++\n\\[inline-highlighting\\]\ndisable = true\n+++\n\n<code class=\"hljs language-rust\">x</code>",
+            highlight_inline(&format!("{front_matter}\n\n`[rust] x`"), &config),
+        );
+    }
+
+    #[test]
+    fn class_slugified_and_data_lang_escaped() {
+        let config = Configuration {
+            data_lang: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-my-lang--x-\" data-lang=\"my lang&quot;&lt;x&gt;\">x</code>",
+            inline_with_highlighting("x", "my lang\"<x>", "x", &[], &config),
+        );
+        assert_eq!(
+            "<code class=\"hljs language-c++\" data-lang=\"c++\">x</code>",
+            inline_with_highlighting("x", "c++", "x", &[], &config),
+        );
+    }
+
+    #[test]
+    fn noscript_fallback() {
+        assert_eq!(
+            "<code class=\"hljs language-rust\">x</code>",
+            inline_with_highlighting("x", "rust", "x", &[], &Configuration::default()),
+        );
+        let config = Configuration {
+            noscript_fallback: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<span class=\"noscript-fallback\"><code class=\"hljs language-rust\">x</code><noscript><code>x</code></noscript></span>",
+            inline_with_highlighting("x", "rust", "x", &[], &config),
+        );
+        assert_eq!(
+            "<span class=\"noscript-fallback\"><code class=\"hljs language-rust\">Vec&lt;u8&gt;</code><noscript><code>Vec&lt;u8&gt;</code></noscript></span>",
+            highlight_inline("`[rust] Vec<u8>`", &config),
+        );
+        // A reader without JavaScript sees the copy only, since the stylesheet in the
+        // README hides the other children of the fallback.
+        let hidden = Regex::new(r#"<span class="noscript-fallback">.*?<noscript>"#).unwrap();
+        let tags = Regex::new("<[^>]*>").unwrap();
+        let html = render(&highlight_inline("`[rust] Vec<u8>`", &config));
+        assert_eq!(
+            "Vec&lt;u8&gt;\n",
+            tags.replace_all(&hidden.replace_all(&html, ""), "")
+        );
+    }
+
+    #[test]
+    fn lang_directive_scoped_to_next_block() {
+        assert_eq!(
+            "<!-- highlight-lang: rust -->\n\nFirst <code class=\"hljs language-rust\">x</code>.\n\nSecond <code class=\"hljs language-javascript\">y</code>.",
+            highlight_inline(
+                "<!-- highlight-lang: rust -->\nFirst `x`.\n\nSecond `y`.",
+                &Configuration {
+                    default_language: Some("javascript".to_string()),
+                    ..Default::default()
+                }
+            ),
+        );
+        assert_eq!(
+            "<!-- highlight-lang: rust -->\n\n* <code class=\"hljs language-rust\">x</code>\n* <code class=\"hljs language-rust\">y</code>\n\n`z`",
+            highlight_inline(
+                "<!-- highlight-lang: rust -->\n- `x`\n- `y`\n\n`z`",
+                &Configuration::default()
+            ),
+        );
+    }
+
+    #[test]
+    fn data_element() {
+        let config = Configuration {
+            data_element: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<data value=\"rust\"><code class=\"hljs language-rust\">x</code></data>",
+            inline_with_highlighting("x", "rust", "x", &[], &config),
+        );
+        assert_eq!(
+            "<code class=\"hljs\">x</code>",
+            inline_with_highlighting("x", "auto", "x", &[], &config),
+        );
+        assert_eq!(
+            "<code class=\"hljs language-rust\">x</code>",
+            inline_with_highlighting("x", "rust", "x", &[], &Configuration::default()),
+        );
+    }
+
+    #[test]
+    fn doubled_delimiter_escape() {
+        let config = Configuration {
+            doubled_delimiter_escape: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Inline::Code("[rust] x".to_string()),
+            parse_inline_code("[[rust] x", None, vec![], &config, &Chapter::default()),
+        );
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-python\">[rust] x</code>".to_string(),
+                language: "python".to_string()
+            },
+            parse_inline_code(
+                "[[rust] x",
+                Some("python"),
+                vec![],
+                &config,
+                &Chapter::default()
+            ),
+        );
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language--rust\">x</code>".to_string(),
+                language: "[rust".to_string()
+            },
+            parse_inline_code(
+                "[[rust] x",
+                None,
+                vec![],
+                &Configuration::default(),
+                &Chapter::default()
+            ),
+        );
+    }
+
+    /// Renders markdown the way mdBook would, to check the serialized output.
+    fn render(markdown: &str) -> String {
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, new_cmark_parser(markdown, false, false, false));
+        html
+    }
+
+    #[test]
+    fn markdown_in_html_text() {
+        let config = Configuration::default();
+        let output = highlight_inline(r"`[rust] *a* _b_ [c](d) \. \$ <e>`", &config);
+        assert_eq!(
+            "<p><code class=\"hljs language-rust\">*a* _b_ [c](d) \\. \\$ &lt;e&gt;</code></p>\n",
+            render(&output)
+        );
+        let output = highlight_inline(r"`\[rust] *a*`", &config);
+        assert_eq!("<p><code>[rust] *a*</code></p>\n", render(&output));
+    }
+
+    #[test]
+    fn quote_after_escape_char() {
+        let config = Configuration {
+            noscript_fallback: true,
+            clipboard_source: true,
+            ..Default::default()
+        };
+        let output = highlight_inline(r#"`[rust] "a\"b"`"#, &config);
+        let escaped = r#""a\"b""#;
+        assert_eq!(
+            format!(
+                "<p><span><span class=\"noscript-fallback\"><code class=\"hljs language-rust\">{escaped}</code>\
+                 <noscript><code>{escaped}</code></noscript></span>\
+                 <span class=\"clipboard-src\" hidden=\"\">{escaped}</span></span></p>\n"
+            ),
+            render(&output)
+        );
+        let config = Configuration {
+            emission: Emission::EntityEncode,
+            ..Default::default()
+        };
+        let output = highlight_inline(r#"`[rust] "\"`"#, &config);
+        assert_eq!(
+            "<p>&lt;code class=\"hljs language-rust\"&gt;&amp;quot;\\&amp;quot;&lt;/code&gt;</p>\n",
+            render(&output)
+        );
+    }
+
+    #[test]
+    fn inside_emphasis() {
+        let output = highlight_inline("*`[rust] x`* and **`[rust] y`**", &Configuration::default());
+        assert_eq!(
+            "*<code class=\"hljs language-rust\">x</code>* and **<code class=\"hljs language-rust\">y</code>**",
+            output
+        );
+        assert_eq!(
+            "<p><em><code class=\"hljs language-rust\">x</code></em> and <strong><code class=\"hljs language-rust\">y</code></strong></p>\n",
+            render(&output)
+        );
+    }
+
+    #[test]
+    fn original_as_title() {
+        let config = Configuration {
+            original_as_title: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-rust\" title=\"[rust] a&lt;&quot;b&quot;\">a&lt;&quot;b&quot;</code>"
+                    .to_string(),
+                language: "rust".to_string()
+            },
+            parse_inline_code("[rust] a<\"b\"", None, vec![], &config, &Chapter::default()),
+        );
+    }
+
+    #[test]
+    fn record_original_language() {
+        let config = Configuration {
+            record_original_language: true,
+            aliases: [("js".to_string(), "javascript".to_string())].into(),
+            known_languages: vec!["javascript".to_string()],
+            bare_language: true,
+            ..Default::default()
+        };
+        let html = "<code class=\"hljs language-javascript\" data-original-lang=\"js\">x</code>";
+        let expected = Inline::Html {
+            html: html.to_string(),
+            language: "javascript".to_string(),
+        };
+        assert_eq!(
+            expected,
+            parse_inline_code("[js] x", None, vec![], &config, &Chapter::default())
+        );
+        assert_eq!(
+            expected,
+            parse_inline_code("js x", None, vec![], &config, &Chapter::default())
+        );
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-rust\">x</code>".to_string(),
+                language: "rust".to_string(),
+            },
+            parse_inline_code(
+                "[none] x",
+                Some("rust"),
+                vec![],
+                &config,
+                &Chapter::default()
+            )
+        );
+    }
+
+    #[test]
+    fn language_separator() {
+        let config = Configuration {
+            language_separators: [("diff".to_string(), "::".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-diff\"> +a</code>".to_string(),
+                language: "diff".to_string()
+            },
+            parse_inline_code("[diff]:: +a", None, vec![], &config, &Chapter::default()),
+        );
+        assert_eq!(
+            Inline::Code("[diff] +a".to_string()),
+            parse_inline_code("[diff] +a", None, vec![], &config, &Chapter::default()),
+        );
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-rust\">a</code>".to_string(),
+                language: "rust".to_string()
+            },
+            parse_inline_code("[rust] a", None, vec![], &config, &Chapter::default()),
+        );
+        assert_eq!("`[diff]::+a`", canonicalize("`[diff]+a`", &config));
+    }
+
+    #[test]
+    fn separator_class() {
+        let mut code = None;
+        let records = logging::capture::logged(|| {
+            let config = Configuration::default();
+            code = Some(parse_inline_code(
+                "[rust]\u{a0}a",
+                None,
+                vec![],
+                &config,
+                &Chapter::default(),
+            ));
+        });
+        assert_eq!(Some(Inline::Code("[rust]\u{a0}a".to_string())), code);
+        assert!(records[0].1.contains("missing separator"));
+        let records = logging::capture::logged(|| {
+            let config = Configuration::default();
+            code = Some(parse_inline_code(
+                "[rust]\ta",
+                None,
+                vec![],
+                &config,
+                &Chapter::default(),
+            ));
+        });
+        assert_eq!(Some(Inline::Code("[rust]\ta".to_string())), code);
+        assert!(records[0].1.contains("missing separator"));
+        let config = Configuration {
+            separator_class: SeparatorClass::Ascii,
+            ..Default::default()
+        };
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-rust\">a</code>".to_string(),
+                language: "rust".to_string()
+            },
+            parse_inline_code("[rust]\ta", None, vec![], &config, &Chapter::default()),
+        );
+        let config = Configuration {
+            separator_class: SeparatorClass::Unicode,
+            ..Default::default()
+        };
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-rust\">a</code>".to_string(),
+                language: "rust".to_string()
+            },
+            parse_inline_code("[rust]\u{a0}a", None, vec![], &config, &Chapter::default()),
+        );
+        assert_eq!("`[rust] a`", canonicalize("`[rust]\u{a0}a`", &config));
+    }
+
+    /// Serializes tests running the preprocessor, as some change the environment.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn context(book_toml: &str) -> PreprocessorContext {
+        PreprocessorContext::new(
+            Default::default(),
+            book_toml.parse().unwrap(),
+            "html".to_string(),
+        )
+    }
+
+    fn chapter(name: &str, content: &str) -> BookItem {
+        BookItem::Chapter(Chapter::new(
+            name,
+            content.to_string(),
+            format!("{name}.md"),
+            vec![],
+        ))
+    }
+
+    #[test]
+    fn report_languages() {
+        let ctx = context(
+            r#"
+            [preprocessor.inline-highlighting]
+            default-language = "python"
+            report-languages = true
+            "#,
+        );
+        let book = Book::new_with_items(vec![
+            chapter("a", "`[rust] x` `[auto] y`"),
+            chapter("b", "`z` `[none] w` `[rust] v`"),
+        ]);
+        let _env = ENV.lock().unwrap();
+        let records = logging::capture::logged(|| {
+            InlineHighlighterPreprocessor.run(&ctx, book).unwrap();
+        });
+        assert_eq!(
+            vec![(
+                Level::Info,
+                r#"languages used: ["python","rust"]"#.to_string()
+            )],
+            records
+        );
+    }
+
+    #[test]
+    fn delimiter_only() {
+        let parse = |code, default_language| {
+            let mut inline = None;
+            let records = logging::capture::logged(|| {
+                inline = Some(parse_inline_code(
+                    code,
+                    default_language,
+                    vec![],
+                    &Configuration::default(),
+                    &Chapter::default(),
+                ));
+            });
+            (inline.unwrap(), records.len())
+        };
+        for code in ["[]", "[] x", "[]]", "][", "]"] {
+            assert_eq!((Inline::Code(code.to_string()), 0), parse(code, None));
+            assert_eq!(
+                (
+                    Inline::Html {
+                        html: format!("<code class=\"hljs language-js\">{code}</code>"),
+                        language: "js".to_string()
+                    },
+                    0
+                ),
+                parse(code, Some("js"))
+            );
+        }
+        for code in ["[", "[["] {
+            assert_eq!((Inline::Code(code.to_string()), 1), parse(code, None));
+        }
+    }
+
+    #[test]
+    fn language_marking() {
+        let html = |language_marking| {
+            let config = Configuration {
+                language_marking,
+                ..Default::default()
+            };
+            inline_with_highlighting("x", "rust", "[rust] x", &[], &config)
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\">x</code>",
+            html(LanguageMarking::Class)
+        );
+        assert_eq!(
+            "<code class=\"hljs\" data-language=\"rust\">x</code>",
+            html(LanguageMarking::Attribute)
+        );
+        assert_eq!(
+            "<code class=\"hljs language-rust\" data-language=\"rust\">x</code>",
+            html(LanguageMarking::Both)
+        );
+    }
+
+    #[test]
+    fn inside_blockquote() {
+        let output = highlight_inline(
+            "> quoted `[rust] foo`\n> more `[rust] bar`\n>\n> second",
+            &Configuration::default(),
+        );
+        assert_eq!(
+            "\n > \n > quoted <code class=\"hljs language-rust\">foo</code>\n > more <code class=\"hljs language-rust\">bar</code>\n > \n > second",
+            output
+        );
+        assert_eq!(
+            "<blockquote>\n<p>quoted <code class=\"hljs language-rust\">foo</code>\nmore <code class=\"hljs language-rust\">bar</code></p>\n<p>second</p>\n</blockquote>\n",
+            render(&output)
+        );
+    }
+
+    #[test]
+    fn warn_unused_default() {
+        let config = Configuration {
+            default_language: Some("rust".to_string()),
+            warn_unused_default: true,
+            ..Default::default()
+        };
+        let records = logging::capture::logged(|| {
+            highlight_inline("No code here.", &config);
+        });
+        assert_eq!(1, records.len());
+        assert_eq!(Level::Info, records[0].0);
+        let records = logging::capture::logged(|| {
+            highlight_inline("Some `code` here.", &config);
+        });
+        assert!(records.is_empty());
+        let config = Configuration {
+            warn_unused_default: true,
+            ..Default::default()
+        };
+        let records = logging::capture::logged(|| {
+            highlight_inline("<!-- highlight-lang: rust -->\nNo code here.", &config);
+        });
+        assert_eq!(1, records.len());
+        let records = logging::capture::logged(|| {
+            highlight_inline("<!-- highlight-lang: rust -->\nSome `code` here.", &config);
+        });
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn preserve_fence_char() {
+        let input = "~~~rust\nlet x;\n~~~\n\n`[rust] x`\n\n```\nlet y;\n```";
+        assert_eq!(
+            "\n````rust\nlet x;\n````\n\n<code class=\"hljs language-rust\">x</code>\n\n````\nlet y;\n````",
+            highlight_inline(input, &Configuration::default())
+        );
+        let config = Configuration {
+            preserve_fence_char: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "\n~~~~rust\nlet x;\n~~~~\n\n<code class=\"hljs language-rust\">x</code>\n\n````\nlet y;\n````",
+            highlight_inline(input, &config)
+        );
+    }
+
+    #[test]
+    fn code_pattern() {
+        let config = Configuration {
+            code_pattern: Some(Regex::new(r"^[A-Za-z_]+\(\)$").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-rust\">main()</code>".to_string(),
+                language: "rust".to_string()
+            },
+            parse_inline_code("main()", Some("rust"), vec![], &config, &Chapter::default()),
+        );
+        assert_eq!(
+            Inline::Code("cargo build".to_string()),
+            parse_inline_code(
+                "cargo build",
+                Some("rust"),
+                vec![],
+                &config,
+                &Chapter::default()
+            ),
+        );
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-bash\">cargo build</code>".to_string(),
+                language: "bash".to_string()
+            },
+            parse_inline_code(
+                "[bash] cargo build",
+                Some("rust"),
+                vec![],
+                &config,
+                &Chapter::default()
+            ),
+        );
+    }
+
+    #[test]
+    fn preserve_whitespace() {
+        let config = Configuration {
+            preserve_whitespace: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\" style=\"white-space: pre;\">a   b</code>",
+            inline_with_highlighting("a   b", "rust", "[rust] a   b", &[], &config),
+        );
+        let config = Configuration {
+            preserve_whitespace_class: Some("ws-pre".to_string()),
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust ws-pre\">a   b</code>",
+            inline_with_highlighting("a   b", "rust", "[rust] a   b", &[], &config),
+        );
+    }
+
+    #[test]
+    fn language_groups() {
+        let config = Configuration {
+            language_groups: BTreeMap::from([("python".to_string(), "scripting".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-python group-scripting\">x</code>",
+            inline_with_highlighting("x", "python", "[python] x", &[], &config),
+        );
+        assert_eq!(
+            "<code class=\"hljs language-rust\">x</code>",
+            inline_with_highlighting("x", "rust", "[rust] x", &[], &config),
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn book_matches_sequential_highlighting() {
+        let config = Configuration {
+            default_language: Some("python".to_string()),
+            ..Default::default()
+        };
+        let mut parent = chapter("a", "`[rust] x` `y` `[rust]x`");
+        if let BookItem::Chapter(parent) = &mut parent {
+            parent.sub_items = (0..20)
+                .map(|i| chapter(&format!("a{i}"), &format!("`[c] {i}` `z` `[c]{i}`")))
+                .collect();
+        }
+        let mut book = Book::new_with_items(vec![parent, chapter("b", "`[auto] w`")]);
+        let mut sequential = book.clone();
+
+        let mut reports = vec![];
+        let mut parallel_records =
+            logging::capture::logged(|| reports = highlight_items(&mut book.items, &config, None));
+        let mut sequential_reports = vec![];
+        let mut sequential_records = logging::capture::logged(|| {
+            sequential.for_each_mut(|item| {
+                if let BookItem::Chapter(chapter) = item {
+                    sequential_reports.push(highlight_chapter(chapter, &config));
+                }
+            })
+        });
+        assert_eq!(21, sequential_records.len());
+        parallel_records.sort();
+        sequential_records.sort();
+        assert_eq!(sequential_records, parallel_records);
+
+        let contents = |book: &Book| {
+            book.chapters()
+                .map(|chapter| chapter.content.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(contents(&sequential), contents(&book));
+        let languages = |reports: Vec<ChapterReport>| {
+            reports
+                .into_iter()
+                .flat_map(|report| report.languages)
+                .fold(
+                    BTreeMap::<_, usize>::new(),
+                    |mut languages, (language, n)| {
+                        *languages.entry(language).or_default() += n;
+                        languages
+                    },
+                )
+        };
+        assert_eq!(languages(sequential_reports), languages(reports));
+    }
+
+    #[test]
+    fn source_position_attrs() {
+        let config = Configuration {
+            source_position_attrs: true,
+            data_element: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "# Title\n\ntext\n<data value=\"rust\"><code class=\"hljs language-rust\" data-source-line=\"4\">x</code></data> and\n<data value=\"c\"><code class=\"hljs language-c\" data-source-line=\"5\">y</code></data>",
+            highlight_inline("# Title\n\ntext\n`[rust] x` and\n`[c] y`", &config),
+        );
+        // The attribute belongs to the element of the code, not to what wraps it.
+        let config = Configuration {
+            source_position_attrs: true,
+            noscript_fallback: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<span class=\"noscript-fallback\"><code class=\"hljs language-rust\" data-source-line=\"1\">x</code>\
+             <noscript><code>x</code></noscript></span> <mark><code data-source-line=\"1\">y</code></mark>",
+            highlight_inline("`[rust] x` `[mark] y`", &config),
+        );
+    }
+
+    #[test]
+    fn doc_links() {
+        let config = Configuration {
+            doc_links: [(
+                "rust".to_string(),
+                "https://doc.rust-lang.org/std/?search={code}".to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<a href=\"https://doc.rust-lang.org/std/?search=Vec%3A%3Anew\">\
+             <code class=\"hljs language-rust\">Vec&#58;&#58;new</code></a> <code class=\"hljs language-py\">len</code>",
+            highlight_inline("`[rust] Vec::new` `[py] len`", &config)
+        );
+    }
+
+    #[test]
+    fn css_var_map() {
+        let config = Configuration {
+            css_var_map: [("rust".to_string(), "--rust-color".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\" style=\"--lang-color: var(--rust-color)\">a</code> \
+             <code class=\"hljs language-py\">b</code>",
+            highlight_inline("`[rust] a` `[py] b`", &config)
+        );
+        let config = Configuration {
+            preserve_whitespace: true,
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\" style=\"white-space: pre; --lang-color: var(--rust-color)\">a</code>",
+            highlight_inline("`[rust] a`", &config)
+        );
+    }
+
+    #[test]
+    fn copy_button_attr() {
+        let config = Configuration {
+            data_lang: true,
+            original_as_title: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\" data-lang=\"rust\" title=\"[rust] a\">a</code>",
+            inline_with_highlighting("a", "rust", "[rust] a", &[], &config),
+        );
+        let config = Configuration {
+            copy_button_attr: Some("data-copy".to_string()),
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\" data-lang=\"rust\" data-copy=\"true\" title=\"[rust] a\">a</code>",
+            inline_with_highlighting("a", "rust", "[rust] a", &[], &config),
+        );
+    }
+
+    #[test]
+    fn inner_wrapper_class() {
+        let config = Configuration {
+            inner_wrapper_class: Some("ws-pre".to_string()),
+            language_affixes: [(
+                "pycon".to_string(),
+                Affixes {
+                    prefix: ">>> ".to_string(),
+                    suffix: String::new(),
+                },
+            )]
+            .into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\"><span class=\"ws-pre\">  indented</span></code>",
+            inline_with_highlighting("  indented", "rust", "[rust]   indented", &[], &config),
+        );
+        assert_eq!(
+            "<code class=\"hljs language-pycon\"><span class=\"ws-pre\">&gt;&gt;&gt; x</span></code>",
+            inline_with_highlighting("x", "pycon", "[pycon] x", &[], &config),
+        );
+    }
+
+    #[test]
+    fn strip_trailing_punct() {
+        let config = Configuration::default();
+        assert_eq!(
+            "<code class=\"hljs language-rust\">let x = 1;</code>",
+            inline_with_highlighting("let x = 1;", "rust", "[rust] let x = 1;", &[], &config),
+        );
+        let config = Configuration {
+            strip_trailing_punct: Some(";,".to_string()),
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\">let x = 1</code>",
+            inline_with_highlighting("let x = 1;", "rust", "[rust] let x = 1;", &[], &config),
+        );
+        assert_eq!(
+            "<code class=\"hljs language-rust\">a, b</code>",
+            inline_with_highlighting("a, b,;", "rust", "[rust] a, b,;", &[], &config),
+        );
+    }
+
+    #[test]
+    fn tab_replacement() {
+        let config = Configuration::default();
+        assert_eq!(
+            "<code class=\"hljs language-make\">\tcc</code>",
+            inline_with_highlighting("\tcc", "make", "[make] \tcc", &[], &config),
+        );
+        let config = Configuration {
+            tab_replacement: Some("    ".to_string()),
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"hljs language-make\">    cc    </code>",
+            inline_with_highlighting("\tcc\t", "make", "[make] \tcc\t", &[], &config),
+        );
+    }
+
+    #[test]
+    fn disabled_by_env() {
+        let ctx = context("");
+        let book = Book::new_with_items(vec![chapter("a", "`[rust] x`")]);
+        let run = |book| {
+            let book = InlineHighlighterPreprocessor.run(&ctx, book).unwrap();
+            book.chapters().next().unwrap().content.clone()
+        };
+        let _env = ENV.lock().unwrap();
+        // SAFETY: tests touching the environment hold `ENV`.
+        unsafe { std::env::set_var(DISABLE_ENV, "1") };
+        let disabled = run(book.clone());
+        unsafe { std::env::set_var(DISABLE_ENV, "0") };
+        let enabled = run(book);
+        unsafe { std::env::remove_var(DISABLE_ENV) };
+        assert_eq!("`[rust] x`", disabled);
+        assert_eq!("<code class=\"hljs language-rust\">x</code>", enabled);
+    }
+
+    #[test]
+    fn empty_code_policy() {
+        let highlight = |empty_code_policy| {
+            let config = Configuration {
+                empty_code_policy,
+                ..Default::default()
+            };
+            highlight_inline("a `[rust] ` b", &config)
+        };
+        assert_eq!(
+            "a <code class=\"hljs language-rust\"></code> b",
+            highlight(EmptyCodePolicy::EmitEmpty)
+        );
+        assert_eq!("a  b", highlight(EmptyCodePolicy::Skip));
+        assert_eq!("a <code></code> b", highlight(EmptyCodePolicy::Plain));
+    }
+
+    #[test]
+    fn list_tightness() {
+        let config = Configuration::default();
+        let tight = highlight_inline("- `[rust] a`\n- b\n", &config);
+        assert_eq!(
+            "<ul>\n<li><code class=\"hljs language-rust\">a</code></li>\n<li>b</li>\n</ul>\n",
+            render(&tight)
+        );
+        let loose = highlight_inline("1. `[rust] a`\n\n2. b\n", &config);
+        assert_eq!(
+            "<ol>\n<li>\n<p><code class=\"hljs language-rust\">a</code></p>\n</li>\n<li>\n<p>b</p>\n</li>\n</ol>\n",
+            render(&loose)
+        );
+    }
+
+    #[test]
+    fn final_class_map() {
+        let config = Configuration {
+            final_class_map: BTreeMap::from([("rust".to_string(), "ds-code-rs".to_string())]),
+            language_groups: BTreeMap::from([("rust".to_string(), "systems".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"ds-code-rs\">x</code>",
+            inline_with_highlighting("x", "rust", "[rust] x", &[], &config),
+        );
+        assert_eq!(
+            "<code class=\"hljs language-c\">x</code>",
+            inline_with_highlighting("x", "c", "[c] x", &[], &config),
+        );
+    }
+
+    #[test]
+    fn warn_on_large_output() {
+        let config = Configuration {
+            warn_on_large_output: Some(100),
+            ..Default::default()
+        };
+        let records = logging::capture::logged(|| {
+            highlight_inline("`[rust] a` `[rust] b`", &config);
+        });
+        assert!(records.is_empty());
+        let records = logging::capture::logged(|| {
+            highlight_inline("`[rust] a` `[rust] b` `[rust] c`", &config);
+        });
+        assert_eq!(1, records.len());
+        assert_eq!(Level::Warn, records[0].0);
+        assert!(records[0].1.contains("grew from 32 to 125 bytes"));
+    }
+
+    #[test]
+    fn diff_line_classes() {
+        let config = Configuration::default();
+        assert_eq!(
+            "<code class=\"hljs language-diff diff-add\">&#43;added</code>",
+            highlight_inline("`[diff] +added`", &config)
+        );
+        assert_eq!(
+            "<code class=\"hljs language-diff diff-del\">&#45;removed</code>",
+            highlight_inline("`[diff] -removed`", &config)
+        );
+        assert_eq!(
+            "<code class=\"hljs language-diff\"> context</code>",
+            highlight_inline("`[diff]  context`", &config)
+        );
+    }
+
+    #[test]
+    fn process_book_keeps_input() {
+        let book = Book::new_with_items(vec![chapter("a", "`[rust] x`")]);
+        let processed = process_book(&book, &Configuration::default()).unwrap();
+        let content = |book: &Book| book.chapters().next().unwrap().content.clone();
+        assert_eq!("`[rust] x`", content(&book));
+        assert_eq!(
+            "<code class=\"hljs language-rust\">x</code>",
+            content(&processed)
+        );
+
+        // Like the preprocessor, it rejects languages outside of `allowed-languages`.
+        let config = Configuration {
+            allowed_languages: vec!["py".to_string()],
+            config_strictness: Strictness::Strict,
+            ..Default::default()
+        };
+        assert!(process_book(&book, &config).is_err());
+    }
+
+    #[test]
+    fn details_and_summary() {
+        let config = Configuration::default();
+        // Code within an HTML block is raw HTML, markdown only starts after a blank line.
+        let markdown = "<details>\n<summary>`[rust] a`</summary>\n\n`[rust] b`\n\n</details>\n";
+        assert_eq!(
+            "<details>\n<summary>`[rust] a`</summary>\n\n<code class=\"hljs language-rust\">b</code>\n\n</details>\n",
+            highlight_inline(markdown, &config)
+        );
+        let markdown = "<details>\n<summary>\n\n`[rust] a`\n\n</summary>\n\nb\n\n</details>\n";
+        assert_eq!(
+            "<details>\n<summary>\n<p><code class=\"hljs language-rust\">a</code></p>\n</summary>\n<p>b</p>\n</details>\n",
+            render(&highlight_inline(markdown, &config))
+        );
+    }
+
+    #[test]
+    fn auto_detect_class_only_for_auto() {
+        let config = Configuration {
+            auto_class: Some("hljs detected".to_string()),
+            default_language: Some("auto".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs detected\">a</code> <code class=\"hljs language-rust\">b</code>",
+            highlight_inline("`a` `[rust] b`", &config)
+        );
+    }
+
+    #[test]
+    fn none_any_case() {
+        let config = Configuration {
+            default_language: Some("python".to_string()),
+            ..Default::default()
+        };
+        for none in ["none", "None", "NONE"] {
+            assert_eq!(
+                "<code class=\"hljs language-python\">x</code>",
+                highlight_inline(&format!("`[{none}] x`"), &config)
+            );
+        }
+    }
+
+    #[test]
+    fn link_like_spec() {
+        let mut output = None;
+        let records = logging::capture::logged(|| {
+            output = Some(highlight_inline(
+                "`[text](url) stuff`",
+                &Configuration::default(),
+            ));
+        });
+        assert_eq!("`[text](url) stuff`", output.unwrap());
+        assert_eq!(1, records.len());
+        assert_eq!(Level::Warn, records[0].0);
+        assert!(records[0].1.contains("looks like a markdown link"));
+        assert_eq!(
+            "`[text](url) stuff`",
+            canonicalize("`[text](url) stuff`", &Configuration::default())
+        );
+    }
+
+    #[test]
+    fn validate_html() {
+        let config = Configuration {
+            validate_html: true,
+            noscript_fallback: true,
+            source_position_attrs: true,
+            ..Default::default()
+        };
+        let records = logging::capture::logged(|| {
+            highlight_inline("`[rust] x` `[auto] y`", &config);
+        });
+        assert!(records.is_empty());
+        // The code is escaped.
+        let records = logging::capture::logged(|| {
+            highlight_inline("`[html] <b>bold` `[rust] Vec<u8>`", &config);
+        });
+        assert!(records.is_empty());
+        let config = Configuration {
+            element: Some("code x".to_string()),
+            ..config
+        };
+        let records = logging::capture::logged(|| {
+            highlight_inline("`[rust] x`", &config);
+        });
+        assert_eq!(1, records.len());
+        assert_eq!(Level::Error, records[0].0);
+        assert!(records[0].1.contains("`[rust] x`"));
+
+        assert!(is_well_formed("<a b=\"c\"><d>e</d></a>"));
+        assert!(!is_well_formed("<a b=c>e</a>"));
+        assert!(!is_well_formed("<a>e</a><a>f</a>"));
+        assert!(!is_well_formed("<a><b>e</a></b>"));
+        assert!(!is_well_formed("e<a></a>"));
+        assert!(!is_well_formed(""));
+    }
+
+    #[test]
+    fn inside_bold_link() {
+        let output = highlight_inline(
+            "[**`[rust] x`**](https://example.com)",
+            &Configuration::default(),
+        );
+        assert_eq!(
+            "<p><a href=\"https://example.com\"><strong><code class=\"hljs language-rust\">x</code></strong></a></p>\n",
+            render(&output)
+        );
+    }
+
+    #[test]
+    fn mark_language() {
+        assert_eq!(
+            "<mark><code>important</code></mark> <code class=\"hljs language-rust\">x</code>",
+            highlight_inline("`[mark] important` `[rust] x`", &Configuration::default())
+        );
+        let config = Configuration {
+            mark_language: Some("hl".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-mark\">important</code> <mark><code>x</code></mark>",
+            highlight_inline("`[mark] important` `[hl] x`", &config)
+        );
+        let config = Configuration {
+            mark_language: Some(String::new()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-mark\">important</code>",
+            highlight_inline("`[mark] important`", &config)
+        );
+    }
+
+    #[test]
+    fn smart_punctuation_keeps_code_quotes() {
+        let config = Configuration {
+            smart_punctuation: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "`\"quoted\"` <code class=\"hljs language-rust\">&#39;a&#39;</code> “prose”",
+            highlight_inline("`\"quoted\"` `[rust] 'a'` \"prose\"", &config)
+        );
+    }
+
+    #[test]
+    fn configured_preprocessor() {
+        let preprocessor = ConfiguredInlineHighlighter::new(Configuration {
+            default_language: Some("python".to_string()),
+            ..Default::default()
+        });
+        // The configuration of `book.toml` is ignored.
+        let ctx = context(
+            r#"
+            [preprocessor.inline-highlighting]
+            default-language = "rust"
+            "#,
+        );
+        let book = Book::new_with_items(vec![chapter("a", "`x`")]);
+        let _env = ENV.lock().unwrap();
+        let book = preprocessor.run(&ctx, book).unwrap();
+        assert_eq!(
+            "<code class=\"hljs language-python\">x</code>",
+            book.chapters().next().unwrap().content
+        );
+        assert_eq!("inline-highlighting", preprocessor.name());
+    }
+
+    #[test]
+    fn collapse_escapes() {
+        let highlight = |collapse_escapes, markdown| {
+            let config = Configuration {
+                collapse_escapes,
+                ..Default::default()
+            };
+            highlight_inline(markdown, &config)
+        };
+        assert_eq!(
+            "<code>&#91;rust&#93; x</code>",
+            highlight(false, r"`\[rust] x`")
+        );
+        assert_eq!(
+            "<code>&#92;&#91;rust&#93; x</code>",
+            highlight(false, r"`\\[rust] x`")
+        );
+        assert_eq!(
+            "<code>&#92;&#92;&#91;rust&#93; x</code>",
+            highlight(false, r"`\\\[rust] x`")
+        );
+        assert_eq!(
+            "<code>&#91;rust&#93; x</code>",
+            highlight(true, r"`\[rust] x`")
+        );
+        assert_eq!(
+            "<code>&#92;&#91;rust&#93; x</code>",
+            highlight(true, r"`\\[rust] x`")
+        );
+        assert_eq!(
+            "<code>&#92;&#91;rust&#93; x</code>",
+            highlight(true, r"`\\\[rust] x`")
+        );
+    }
+
+    #[test]
+    fn sigil_syntax() {
+        let config = Configuration {
+            syntax: Syntax::Sigil,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\">let s &#61; &quot;&#35;&quot;&#59;</code> `[rust] x` <code>&#35;rust x</code>",
+            highlight_inline("`#rust let s = \"#\";` `[rust] x` `\\#rust x`", &config)
+        );
+        let config = Configuration {
+            sigil: Some('@'),
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"hljs language-js\">a&#64;b</code> `#rust x`",
+            highlight_inline("`@js a@b` `#rust x`", &config)
+        );
+    }
+
+    #[test]
+    fn min_language_length() {
+        let config = Configuration {
+            min_language_length: 2,
+            ..Default::default()
+        };
+        let mut output = None;
+        let records = logging::capture::logged(|| {
+            output = Some(highlight_inline("`[a] b` `[js] c`", &config));
+        });
+        assert_eq!(
+            "`[a] b` <code class=\"hljs language-js\">c</code>",
+            output.unwrap()
+        );
+        assert_eq!(1, records.len());
+        assert_eq!(Level::Debug, records[0].0);
+    }
+
+    #[test]
+    fn clipboard_source() {
+        let config = Configuration {
+            clipboard_source: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<span><code class=\"hljs language-rust\">a&lt;b</code><span class=\"clipboard-src\" hidden=\"\">a&lt;b</span></span>",
+            inline_with_highlighting("a<b", "rust", "[rust] a<b", &[], &config),
+        );
+        let config = Configuration {
+            validate_html: true,
+            ..config
+        };
+        let records = logging::capture::logged(|| {
+            highlight_inline("`[rust] x`", &config);
+        });
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn language_badge() {
+        let config = Configuration {
+            language_badge: true,
+            aliases: [("rs".to_string(), "rust".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<span><code class=\"hljs language-rust\">a</code><sup class=\"lang-badge\">rust</sup></span> \
+             <code class=\"hljs\">b</code> `c`",
+            highlight_inline("`[rs] a` `[auto] b` `[none] c`", &config)
+        );
+        let config = Configuration {
+            validate_html: true,
+            clipboard_source: true,
+            ..config
+        };
+        let mut output = String::new();
+        let records = logging::capture::logged(|| output = highlight_inline("`[rust] x`", &config));
+        assert!(records.is_empty());
+        assert_eq!(
+            "<span><code class=\"hljs language-rust\">x</code>\
+             <span class=\"clipboard-src\" hidden=\"\">x</span><sup class=\"lang-badge\">rust</sup></span>",
+            output
+        );
+    }
+
+    #[test]
+    fn preserve_emphasis_char() {
+        let markdown = "_italic_ `[rust] x` __bold__ *a* **b** _c **d** e_";
+        assert_eq!(
+            "*italic* <code class=\"hljs language-rust\">x</code> **bold** *a* **b** *c **d** e*",
+            highlight_inline(markdown, &Configuration::default())
+        );
+        let config = Configuration {
+            preserve_emphasis_char: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "_italic_ <code class=\"hljs language-rust\">x</code> __bold__ *a* **b** _c **d** e_",
+            highlight_inline(markdown, &config)
+        );
+    }
+
+    #[test]
+    fn merge_adjacent() {
+        let markdown = "`[rust] a` `[rust] b`\t`[rs]c` `[py] d` `e`";
+        let config = Configuration {
+            aliases: [("rs".to_string(), "rust".to_string())].into(),
+            require_separator: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\">a</code> <code class=\"hljs language-rust\">b</code>\t\
+             <code class=\"hljs language-rust\">c</code> <code class=\"hljs language-py\">d</code> `e`",
+            highlight_inline(markdown, &config)
+        );
+        let config = Configuration {
+            merge_adjacent: true,
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\">a b\tc</code> <code class=\"hljs language-py\">d</code> `e`",
+            highlight_inline(markdown, &config)
+        );
+        // Backticks cannot end one inline code and start another one.
+        assert_eq!(
+            "<code class=\"hljs language-rust\">a&#96;&#96;&#91;rust&#93; b</code>",
+            highlight_inline("`[rust] a``[rust] b`", &Configuration::default())
+        );
+    }
+
+    #[test]
+    fn reference_links() {
+        let markdown = "See [the docs][rust] and `[rust] x`.\n\n\
+                        [rust]: https://www.rust-lang.org \"Title `[rust] y`\"";
+        // The title of the definition is no inline code.
+        assert_eq!(
+            "See [the docs][rust] and <code class=\"hljs language-rust\">x</code>.\n\n\
+             [rust]: https://www.rust-lang.org \"Title `[rust] y`\"",
+            highlight_inline(markdown, &Configuration::default())
+        );
+        // A shortcut reference looking like a language specification is a link.
+        let markdown = "[rust] and `[rust] x`\n\n[rust]: https://www.rust-lang.org";
+        assert_eq!(
+            "[rust] and <code class=\"hljs language-rust\">x</code>\n\n[rust]: https://www.rust-lang.org",
+            highlight_inline(markdown, &Configuration::default())
+        );
+    }
+
+    #[test]
+    fn footnote() {
+        let markdown = "Text[^note] and `[rust] a`.\n\n[^note]: See `[rust] b` and `c`.";
+        assert_eq!(
+            "Text[^note] and <code class=\"hljs language-rust\">a</code>.\n\n\
+             [^note]: See <code class=\"hljs language-rust\">b</code> and `c`.",
+            highlight_inline(markdown, &Configuration::default())
+        );
+    }
+
+    #[test]
+    fn described_by() {
+        let chapter = Chapter::default();
+        let config = Configuration::default();
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-rust\" aria-describedby=\"legend1\">x</code>"
+                    .to_string(),
+                language: "rust".to_string()
+            },
+            parse_inline_code("[rust #legend1] x", None, vec![], &config, &chapter)
+        );
+        assert_eq!(
+            Inline::Html {
+                html: "<code class=\"hljs language-rust\">x</code>".to_string(),
+                language: "rust".to_string()
+            },
+            parse_inline_code("[rust] x", None, vec![], &config, &chapter)
+        );
+        assert_eq!(
+            Inline::Code("x".to_string()),
+            parse_inline_code("[none #legend1] x", None, vec![], &config, &chapter)
+        );
+        let config = Configuration {
+            data_element: true,
+            record_original_language: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            Inline::Html {
+                html: "<data value=\"rust\"><code class=\"hljs language-rust\" \
+                       aria-describedby=\"legend1\" data-original-lang=\"rust\">x</code></data>"
+                    .to_string(),
+                language: "rust".to_string()
+            },
+            parse_inline_code("[rust #legend1] x", None, vec![], &config, &chapter)
+        );
+    }
+
+    #[test]
+    fn normalize_language() {
+        let chapter = Chapter::default();
+        let config = Configuration {
+            aliases: [("js".to_string(), "javascript".to_string())].into(),
+            ..Default::default()
+        };
+        let highlighted = |language: &str| Inline::Html {
+            html: format!("<code class=\"hljs language-{language}\">x</code>"),
+            language: language.to_string(),
+        };
+        assert_eq!(
+            highlighted("JS"),
+            parse_inline_code("[JS] x", None, vec![], &config, &chapter)
+        );
+        let config = Configuration {
+            normalize_language: true,
+            ..config
+        };
+        assert_eq!(
+            highlighted("javascript"),
+            parse_inline_code("[JS] x", None, vec![], &config, &chapter)
+        );
+        assert_eq!(
+            highlighted("ελληνικά"),
+            parse_inline_code("[ΕΛΛΗΝΙΚΆ] x", None, vec![], &config, &chapter)
+        );
+    }
+
+    #[test]
+    fn body_equal_to_language() {
+        let highlighted = |code: &str| Inline::Html {
+            html: format!("<code class=\"hljs language-rust\">{code}</code>"),
+            language: "rust".to_string(),
+        };
+        let chapter = Chapter::default();
+        let config = Configuration::default();
+        assert_eq!(
+            highlighted("rust"),
+            parse_inline_code("[rust] rust", None, vec![], &config, &chapter)
+        );
+        assert_eq!(
+            highlighted("none"),
+            parse_inline_code("[none] none", Some("rust"), vec![], &config, &chapter)
+        );
+        assert_eq!(
+            Inline::Code("none".to_string()),
+            parse_inline_code("[none] none", None, vec![], &config, &chapter)
+        );
+    }
+
+    #[test]
+    fn url_with_brackets_in_body() {
+        assert_eq!(
+            "<code class=\"hljs language-bash\">curl https&#58;&#47;&#47;x&#46;com&#47;&#91;a&#93;</code>",
+            highlight_inline("`[bash] curl https://x.com/[a]`", &Configuration::default())
+        );
+        assert_eq!(
+            "<code class=\"hljs language-bash\">curl https&#58;&#47;&#47;x&#46;com&#47;&#91;a&#93;&#40;b&#41;</code>",
+            highlight_inline(
+                "`[bash] curl https://x.com/[a](b)`",
+                &Configuration::default()
+            )
+        );
+    }
+
+    #[test]
+    fn data_uri_emission() {
+        let config = Configuration {
+            emission: Emission::DataUri,
+            ..Default::default()
+        };
+        assert_eq!(
+            "`data:text/html;charset=utf-8,%3Ccode%20class%3D%22hljs%20language-rust%22%3Ea%3C%2Fcode%3E` `b`",
+            highlight_inline("`[rust] a` `b`", &config)
+        );
+    }
+
+    #[test]
+    fn entity_encode_emission() {
+        let config = Configuration {
+            emission: Emission::EntityEncode,
+            ..Default::default()
+        };
+        assert_eq!(
+            "&lt;code class&#61;&#34;hljs language&#45;rust&#34;&gt;a&lt;&#47;code&gt; `b`",
+            highlight_inline("`[rust] a` `b`", &config)
+        );
+        let config = Configuration {
+            original_as_title: true,
+            ..config
+        };
+        assert_eq!(
+            "&lt;code class&#61;&#34;hljs language&#45;sh&#34; title&#61;&#34;&#91;sh&#93; a &amp;amp&#59; b&#34;&gt;a &amp;amp&#59; b&lt;&#47;code&gt;",
+            highlight_inline("`[sh] a & b`", &config)
+        );
+    }
+
+    #[test]
+    fn jsx_target() {
+        let config = Configuration {
+            target: Target::Jsx,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<Code lang=\"rust\">{`let x = 1;`}</Code>",
+            highlight_inline("`[rust] let x = 1;`", &config)
+        );
+        assert_eq!(
+            "<Code lang=\"js\">{`\\`\\${a}\\` + \\$ + \\\\n`}</Code>",
+            highlight_inline("`` [js] `${a}` + $ + \\n ``", &config)
+        );
+    }
+
+    #[test]
+    fn math() {
+        let markdown = "`[math] x` and $x^2$ and $`[rust] y`$\n\n$$\nz_1 * z_2\n$$";
+        let config = Configuration {
+            math: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-math\">x</code> and $x^2$ and $`[rust] y`$\n\n$$\nz_1 * z_2\n$$",
+            highlight_inline(markdown, &config)
+        );
+        assert_eq!(
+            "`[math] x` and $x^2$ and $`[rust]y`$",
+            canonicalize("`[math]x` and $x^2$ and $`[rust]y`$", &config)
+        );
+    }
+
+    #[test]
+    fn normalize_output() {
+        let markdown = "\u{feff}`[rust] x`\r\nnext line\r\n\r\n<div>\r\na\r\n</div>\r\n";
+        let expected =
+            "<code class=\"hljs language-rust\">x</code>\nnext line\n\n<div>\na\n</div>\n";
+        // Serializing already writes LF line endings but keeps the byte order mark.
+        assert_eq!(
+            format!("\u{feff}{expected}"),
+            highlight_inline(markdown, &Configuration::default())
+        );
+        let config = Configuration {
+            normalize_output: true,
+            ..Default::default()
+        };
+        assert_eq!(expected, highlight_inline(markdown, &config));
+        assert_eq!("a\nb\nc", normalize_line_endings("a\r\nb\rc"));
+    }
+
+    #[test]
+    fn serialize_buffer_capacity() {
+        let markdown = "`[rust] x` text\n\n".repeat(1000);
+        let config = Configuration {
+            serialize_buffer_capacity: Some(1.2),
+            ..Default::default()
+        };
+        assert_eq!(
+            highlight_inline(&markdown, &Configuration::default()),
+            highlight_inline(&markdown, &config)
+        );
+        let events: Vec<_> = new_cmark_parser(&markdown, false, false, false).collect();
+        let result = serialize(&events, &SourceStyle::default(), 100_000).unwrap();
+        assert!(result.capacity() >= 100_000);
+    }
+
+    #[test]
+    fn sections() {
+        let markdown = "`[rust] a`\n\n# Examples\n\n`[rust] b`\n\n## Nested\n\n`[rust] c`\n\n\
+                        # Reference\n\n`[rust] d`";
+        let config = Configuration {
+            sections: vec!["Examples".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            "`[rust] a`\n\n# Examples\n\n<code class=\"hljs language-rust\">b</code>\n\n\
+             ## Nested\n\n<code class=\"hljs language-rust\">c</code>\n\n# Reference\n\n`[rust] d`",
+            highlight_inline(markdown, &config)
+        );
+    }
+
+    #[test]
+    fn preserve_heading_style() {
+        let markdown = "Title `[rust] x`\n===\n\nSection\n---\n\n### Sub";
+        assert_eq!(
+            "# Title <code class=\"hljs language-rust\">x</code>\n\n## Section\n\n### Sub",
+            highlight_inline(markdown, &Configuration::default())
+        );
+        let config = Configuration {
+            preserve_heading_style: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "Title <code class=\"hljs language-rust\">x</code>\n===\n\nSection\n---\n\n### Sub",
+            highlight_inline(markdown, &config)
+        );
+    }
+
+    #[test]
+    fn comment_spec() {
+        let config = Configuration {
+            comment_spec: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\">a &#47;&#42; &lt;&#33;&#45;&#45;b&#45;&#45;&gt; &#42;&#47;</code> <code class=\"hljs language-html\">x &lt;&#33;&#45;&#45;c&#45;&#45;&gt;</code>",
+            highlight_inline(
+                "`<!--rust--> a /* <!--b--> */` `[html] x <!--c-->`",
+                &config
+            )
+        );
+        assert_eq!(
+            "`<!--rust--> a`",
+            highlight_inline("`<!--rust--> a`", &Configuration::default())
+        );
+    }
+
+    #[test]
+    fn stats_chapter() {
+        let config = Configuration {
+            generate_stats_chapter: true,
+            ..Default::default()
+        };
+        let book = Book::new_with_items(vec![
+            chapter("a", "`[rust] x` `[rust] y` `[c] z`"),
+            chapter("b", "`w`"),
+        ]);
+        let book = run_with(&config, None, book).unwrap();
+        let Some(BookItem::Chapter(stats)) = book.items.last() else {
+            panic!("no statistics chapter");
+        };
+        assert_eq!("Language Statistics", stats.name);
+        assert_eq!(
+            Some(std::path::Path::new("language-statistics.md")),
+            stats.path.as_deref()
+        );
+        assert_eq!(
+            "# Language Statistics\n\n| Chapter | Language | Count |\n|---|---|---|\n| a | c | 1 |\n| a | rust | 2 |\n",
+            stats.content
+        );
+    }
+
+    #[test]
+    fn abutting_text() {
+        let output = highlight_inline("`[rust] foo`bar.", &Configuration::default());
+        assert_eq!("<code class=\"hljs language-rust\">foo</code>bar.", output);
+        assert_eq!(
+            "<p><code class=\"hljs language-rust\">foo</code>bar.</p>\n",
+            render(&output)
+        );
+    }
+
+    #[test]
+    fn unclosed_spec_under_default_language() {
+        let config = Configuration {
+            default_language: Some("rust".to_string()),
+            ..Default::default()
+        };
+        let mut output = None;
+        let records = logging::capture::logged(|| {
+            output = Some(highlight_inline("`[not closed`", &config));
+        });
+        assert_eq!(
+            "<code class=\"hljs language-rust\">&#91;not closed</code>",
+            output.unwrap()
+        );
+        assert!(records.is_empty());
+        let records = logging::capture::logged(|| {
+            assert_eq!(
+                "`[not closed`",
+                highlight_inline("`[not closed`", &Configuration::default())
+            );
+        });
+        assert_eq!(1, records.len());
+    }
+
+    #[test]
+    fn language_affixes() {
+        let config = Configuration {
+            language_affixes: BTreeMap::from([(
+                "pycon".to_string(),
+                Affixes {
+                    prefix: ">>> ".to_string(),
+                    suffix: " #".to_string(),
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-pycon\">&gt;&gt;&gt; 1 + 1 #</code>",
+            inline_with_highlighting("1 + 1", "pycon", "[pycon] 1 + 1", &[], &config),
+        );
+        assert_eq!(
+            "<code class=\"hljs language-python\">1 + 1</code>",
+            inline_with_highlighting("1 + 1", "python", "[python] 1 + 1", &[], &config),
+        );
+    }
+
+    #[test]
+    fn detect_misfenced() {
+        let config = Configuration {
+            detect_misfenced: Some(20),
+            ..Default::default()
+        };
+        let markdown = "`[rust] fn main() { println!(); }`\n\nsee `[rust] fn main() { println!(); }`\n\n`[rust] x`";
+        let mut output = None;
+        let records = logging::capture::logged(|| {
+            output = Some(highlight_inline(markdown, &config));
+        });
+        assert_eq!(1, records.len());
+        assert_eq!(Level::Warn, records[0].0);
+        assert_eq!(3, output.unwrap().matches("<code").count());
+
+        let config = Configuration {
+            misfenced_plain: true,
+            ..config
+        };
+        let output = highlight_inline(markdown, &config);
+        assert!(output.starts_with("`[rust] fn main() { println!(); }`\n\nsee <code"));
+        assert_eq!(2, output.matches("<code").count());
+    }
+
+    #[test]
+    fn element_map() {
+        assert_eq!(
+            "<var>x</var> <code class=\"hljs language-rust\">y</code>",
+            highlight_inline("`[var] x` `[rust] y`", &Configuration::default())
+        );
+        let config = Configuration {
+            element_map: BTreeMap::from([
+                ("kbd".to_string(), "kbd".to_string()),
+                ("var".to_string(), String::new()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-var\">x</code> <kbd>y</kbd>",
+            highlight_inline("`[var] x` `[kbd] y`", &config)
+        );
+    }
+
+    #[test]
+    fn require_separator() {
+        let mut output = None;
+        let records = logging::capture::logged(|| {
+            output = Some(highlight_inline("`[rust]code`", &Configuration::default()));
+        });
+        assert_eq!("`[rust]code`", output.unwrap());
+        assert_eq!(1, records.len());
+        let config = Configuration {
+            require_separator: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\">code</code> <code class=\"hljs language-rust\">code</code>",
+            highlight_inline("`[rust]code` `[rust] code`", &config)
+        );
+    }
+
+    #[test]
+    fn theme_aware_plain() {
+        let config = Configuration {
+            theme_aware_plain: true,
+            html_theme: Some("navy".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"plain-navy\">a &lt; b</code> <code class=\"hljs language-rust\">c</code>",
+            highlight_inline("`a < b` `[rust] c`", &config)
+        );
+        let config = Configuration {
+            html_theme: None,
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"plain-light\">a</code>",
+            highlight_inline("`a`", &config)
+        );
+        // Emitted like highlighted code.
+        let config = Configuration {
+            emission: Emission::EntityEncode,
+            ..config
+        };
+        assert_eq!(
+            "&lt;code class&#61;&#34;plain&#45;light&#34;&gt;a &amp;lt&#59; b&lt;&#47;code&gt;",
+            highlight_inline("`a < b`", &config)
+        );
+        let config = Configuration {
+            emission: Emission::RawHtml,
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"plain-light\">&#96;a&#96;</code>",
+            highlight_inline("`` `a` ``", &config)
+        );
+        // Chapters without rewritten plain code are kept as written.
+        let config = Configuration {
+            detect_misfenced: Some(1),
+            misfenced_plain: true,
+            ..config
+        };
+        let records = logging::capture::logged(|| {
+            assert_eq!("_a_\n", highlight_inline("_a_\n", &config));
+            assert_eq!("_a_\n\n`b`\n", highlight_inline("_a_\n\n`b`\n", &config));
+        });
+        assert_eq!(1, records.len());
+    }
+
+    #[test]
+    fn none_and_unmarked_truth_table() {
+        let rust = || Inline::Html {
+            html: "<code class=\"hljs language-rust\">x</code>".to_string(),
+            language: "rust".to_string(),
+        };
+        let plain = |code: &str| Inline::Code(code.to_string());
+        for (code, default_language, highlight_unmarked, expected) in [
+            ("x", None, None, plain("x")),
+            ("x", None, Some(false), plain("x")),
+            ("x", Some("rust"), None, rust()),
+            ("x", Some("rust"), Some(false), plain("x")),
+            ("[none] x", None, None, plain("x")),
+            ("[none] x", None, Some(false), plain("x")),
+            ("[none] x", Some("rust"), None, rust()),
+            ("[none] x", Some("rust"), Some(false), plain("x")),
+        ] {
+            let config = Configuration {
+                highlight_unmarked,
+                ..Default::default()
+            };
+            assert_eq!(
+                expected,
+                parse_inline_code(code, default_language, vec![], &config, &Chapter::default()),
+                "{code} {default_language:?} {highlight_unmarked:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn auto_element() {
+        let config = Configuration {
+            auto_element: Some("span".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<span class=\"hljs\">a</span> <code class=\"hljs language-rust\">b</code>",
+            highlight_inline("`[auto] a` `[rust] b`", &config)
+        );
+        let config = Configuration {
+            element: Some("samp".to_string()),
+            ..config
+        };
+        assert_eq!(
+            "<span class=\"hljs\">a</span> <samp class=\"hljs language-rust\">b</samp>",
+            highlight_inline("`[auto] a` `[rust] b`", &config)
+        );
+    }
+
+    #[test]
+    fn pipe_inside_table() {
+        let markdown = "| `[rust] a \\| b` | c |\n|---|---|\n| `[rust] d \\| e` | `f \\| g` |\n";
+        let config = Configuration {
+            original_as_title: true,
+            ..Default::default()
+        };
+        let output = highlight_inline(markdown, &config);
+        assert_eq!(
+            "<table><thead><tr><th><code class=\"hljs language-rust\" title=\"[rust] a &#124; b\">a | b</code></th><th>c</th></tr></thead><tbody>\n\
+             <tr><td><code class=\"hljs language-rust\" title=\"[rust] d &#124; e\">d | e</code></td><td><code>f | g</code></td></tr>\n</tbody></table>\n",
+            render(&output)
+        );
+    }
+
+    #[test]
+    fn inside_table_header() {
+        let markdown = "| `[rust] a` | b |\n|:---|---:|\n| `[c] c` | d |\n";
+        let output = highlight_inline(markdown, &Configuration::default());
+        assert_eq!(
+            "<table><thead><tr><th style=\"text-align: left\"><code class=\"hljs language-rust\">a</code></th><th style=\"text-align: right\">b</th></tr></thead><tbody>\n<tr><td style=\"text-align: left\"><code class=\"hljs language-c\">c</code></td><td style=\"text-align: right\">d</td></tr>\n</tbody></table>\n",
+            render(&output)
+        );
+    }
+
+    #[test]
+    fn body_strip_markers() {
+        let config = Configuration {
+            body_strip_prefix: Some("«".to_string()),
+            body_strip_suffix: Some("»".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\">a</code> <code class=\"hljs language-rust\">«b</code> <code class=\"hljs language-rust\">c»</code>",
+            highlight_inline("`[rust] «a»` `[rust] «b` `[rust] c»`", &config)
+        );
+    }
+
+    #[test]
+    fn part_titles_and_separators() {
+        // mdBook shows part titles as text, so their inline code stays as written.
+        let book = Book::new_with_items(vec![
+            BookItem::PartTitle("Part `[rust] x`".to_string()),
+            BookItem::Separator,
+            chapter("a", "`[rust] y`"),
+        ]);
+        let book = process_book(&book, &Configuration::default()).unwrap();
+        assert_eq!(
+            vec![
+                BookItem::PartTitle("Part `[rust] x`".to_string()),
+                BookItem::Separator,
+                chapter("a", "<code class=\"hljs language-rust\">y</code>"),
+            ],
+            book.items
+        );
+    }
+
+    #[test]
+    fn allowed_languages() {
+        let book = Book::new_with_items(vec![
+            chapter("a", "`[rust] x` `[auto] y`"),
+            chapter("b", "`z`"),
+        ]);
+        let config = Configuration {
+            allowed_languages: vec!["rust".to_string()],
+            default_language: Some("python".to_string()),
+            ..Default::default()
+        };
+        let records = logging::capture::logged(|| {
+            run_with(&config, None, book.clone()).unwrap();
+        });
+        assert_eq!(
+            vec![(
+                Level::Error,
+                "chapter `b` uses `python`, which is not an allowed language".to_string()
+            )],
+            records
+        );
+        let config = Configuration {
+            config_strictness: Strictness::Strict,
+            ..config
+        };
+        assert!(run_with(&config, None, book.clone()).is_err());
+        let config = Configuration {
+            default_language: None,
+            ..config
+        };
+        assert!(run_with(&config, None, book).is_ok());
+    }
+
+    #[test]
+    fn idempotent() {
+        let content = "# Title `[rust] a`\n\n\
+                       Text `[py] b` and *`[rust] c<d>`* with `plain` code and `[auto] e`.\n\n\
+                       - `[diff] +f`\n- [link `[rust] g`](https://example.com)\n\n\
+                       | `[rust] h` | `i` |\n|---|---|\n\n\
+                       ```rust\nlet x = `[rust] no`;\n```\n\n\
+                       > `[sh] echo \"quoted\" & more`\n\n\
+                       Setext `[rust] j`\n---\n\n~~~\ncode\n~~~\n\n\
+                       _em_ __strong__ \\*not emphasis\\* `` [rust] `k` ``\n\n\
+                       Escaped `\\[rust] l` and `[none] [rust] m` and `\\`.\n";
+        let book = Book::new_with_items(vec![chapter("a", content)]);
+        let configs = [
+            Configuration {
+                default_language: Some("js".to_string()),
+                original_as_title: true,
+                data_lang: true,
+                ..Default::default()
+            },
+            Configuration {
+                preserve_fence_char: true,
+                preserve_emphasis_char: true,
+                preserve_heading_style: true,
+                clipboard_source: true,
+                ..Default::default()
+            },
+        ];
+        for config in &configs {
+            let once = run_with(config, None, book.clone()).unwrap();
+            let content = |book: &Book| book.chapters().next().unwrap().content.clone();
+            assert!(!content(&once).contains("`[rust] l`"));
+            let twice = run_with(config, None, once.clone()).unwrap();
+            assert_eq!(once.items, twice.items);
+        }
+    }
+
+    #[test]
+    fn skip_drafts() {
+        let mut draft = Chapter::new_draft("draft", vec![]);
+        draft.content = "`[rust] x`".to_string();
+        draft.sub_items = vec![chapter("a", "`[rust] y`")];
+        let book = Book::new_with_items(vec![BookItem::Chapter(draft)]);
+        let contents = |config: &Configuration| {
+            let book = run_with(config, None, book.clone()).unwrap();
+            // Unlike `Book::chapters`, this includes drafts.
+            book.iter()
+                .filter_map(|item| match item {
+                    BookItem::Chapter(chapter) => Some(chapter.content.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![
+                "<code class=\"hljs language-rust\">x</code>",
+                "<code class=\"hljs language-rust\">y</code>"
+            ],
+            contents(&Configuration::default())
+        );
+        let config = Configuration {
+            skip_drafts: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["`[rust] x`", "<code class=\"hljs language-rust\">y</code>"],
+            contents(&config)
+        );
+    }
+
+    #[test]
+    fn always_class() {
+        let config = Configuration {
+            always_class: Some("inline-code".to_string()),
+            final_class_map: BTreeMap::from([("c".to_string(), "ds-c".to_string())]),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust inline-code\">a</code> <code class=\"hljs inline-code\">b</code> <code class=\"ds-c inline-code\">c</code>",
+            highlight_inline("`[rust] a` `[auto] b` `[c] c`", &config)
+        );
+    }
+
+    #[test]
+    fn force_ltr() {
+        let config = Configuration {
+            force_ltr: true,
+            element_map: [("kbd".to_string(), "kbd".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\" dir=\"ltr\">a</code> \
+             <mark><code dir=\"ltr\">b</code></mark> <kbd dir=\"ltr\">c</kbd> `d`",
+            highlight_inline("`[rust] a` `[mark] b` `[kbd] c` `d`", &config)
+        );
+    }
+
+    #[test]
+    fn nonce() {
+        let config = Configuration {
+            nonce: Some("{{NONCE}}".to_string()),
+            element_map: [("kbd".to_string(), "kbd".to_string())].into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\" nonce=\"{{NONCE}}\">a</code> \
+             <kbd nonce=\"{{NONCE}}\">b</kbd> `c`",
+            highlight_inline("`[rust] a` `[kbd] b` `c`", &config)
+        );
+    }
+
+    #[test]
+    fn dual_theme_classes() {
+        let config = Configuration {
+            dual_theme_classes: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust hljs-light hljs-dark\">a</code>",
+            highlight_inline("`[rust] a`", &config)
+        );
+        let config = Configuration {
+            theme_classes: vec!["day".to_string(), "night".to_string()],
+            ..config
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust day night\">a</code>",
+            highlight_inline("`[rust] a`", &config)
+        );
+    }
+
+    #[cfg(feature = "token-wrap")]
+    #[test]
+    fn token_wrap() {
+        let config = Configuration {
+            token_wrap: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            "<code class=\"hljs language-rust\"><span class=\"tok\">let</span>  <span class=\"tok\">x;</span> </code>",
+            inline_with_highlighting("let  x; ", "rust", "[rust] let  x; ", &[], &config),
+        );
+    }
 }